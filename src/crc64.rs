@@ -0,0 +1,20 @@
+// CRC-64/Jones, the reflected variant Redis appends to DUMP payloads
+const POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+
+pub fn checksum(bytes: &[u8]) -> u64 {
+    let mut crc = 0u64;
+
+    for &byte in bytes {
+        crc ^= byte as u64;
+
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    crc
+}
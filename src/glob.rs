@@ -0,0 +1,193 @@
+// Redis style glob matching on bytes: * any run, ? any single byte,
+// [abc] / [a-z] / [^...] classes and \ to take the next byte literally,
+// runs in O(pattern * string) since only the last * is ever backtracked to
+pub fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    let mut p = 0;
+    let mut s = 0;
+    // pattern position after the last * and the string position it currently covers up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while s < string.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += pattern[p..]
+                .iter()
+                .take_while(|&&byte| byte == b'*')
+                .count();
+            backtrack = Some((p, s));
+            continue;
+        }
+
+        if let Some(next) = match_single(pattern, p, string[s]) {
+            p = next;
+            s += 1;
+            continue;
+        }
+
+        // let the last * swallow one more byte and retry from there
+        let Some((star_p, star_s)) = backtrack else {
+            return false;
+        };
+
+        p = star_p;
+        s = star_s + 1;
+        backtrack = Some((star_p, s));
+    }
+
+    pattern[p..].iter().all(|&byte| byte == b'*')
+}
+
+// matches one byte against the token at p, returns where the next token starts
+fn match_single(pattern: &[u8], p: usize, byte: u8) -> Option<usize> {
+    let consumed = match pattern.get(p)? {
+        b'?' => 1,
+        b'[' => {
+            let (matched, rest) = match_class(&pattern[p + 1..], byte);
+
+            if !matched {
+                return None;
+            }
+
+            pattern.len() - p - rest.len()
+        }
+        b'\\' if p + 1 < pattern.len() => {
+            if pattern[p + 1] != byte {
+                return None;
+            }
+
+            2
+        }
+        literal if *literal == byte => 1,
+        _ => return None,
+    };
+
+    Some(p + consumed)
+}
+
+// pattern starts right after the opening [, returns whether byte is in the class
+// and what follows the closing ], an unclosed class runs to the end of the pattern
+fn match_class(pattern: &[u8], byte: u8) -> (bool, &[u8]) {
+    let (negate, mut pattern) = match pattern.split_first() {
+        Some((b'^', rest)) => (true, rest),
+        _ => (false, pattern),
+    };
+
+    let mut matched = false;
+
+    loop {
+        match pattern {
+            [] => break,
+            [b']', rest @ ..] => {
+                pattern = rest;
+                break;
+            }
+            [b'\\', escaped, rest @ ..] => {
+                matched |= *escaped == byte;
+                pattern = rest;
+            }
+            [from, b'-', to, rest @ ..] if *to != b']' => {
+                let (low, high) = if from <= to { (from, to) } else { (to, from) };
+                matched |= (*low..=*high).contains(&byte);
+                pattern = rest;
+            }
+            [other, rest @ ..] => {
+                matched |= *other == byte;
+                pattern = rest;
+            }
+        }
+    }
+
+    (matched != negate, pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_patterns() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("*", "", true),
+            ("*", "anything", true),
+            ("user:*", "user:42", true),
+            ("user:*", "session:42", false),
+            ("*:42", "user:42", true),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h[ae]llo", "hallo", true),
+            ("h[ae]llo", "hillo", false),
+            ("key[0-9]", "key7", true),
+            ("key[0-9]", "keyx", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("a\\*b", "a*b", true),
+            ("a\\*b", "axb", false),
+            // an unclosed class runs to the end of the pattern
+            ("a[bc", "ab", true),
+            ("a[bc", "ad", false),
+            // a reversed range covers the same bytes as the ordered one
+            ("[z-a]", "m", true),
+            ("[z-a]", "A", false),
+            // many stars over a long key must not backtrack exponentially
+            (
+                "*a*a*a*a*a*a*a*a*b",
+                "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+                false,
+            ),
+            (
+                "*a*a*a*a*a*a*a*a*",
+                "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+                true,
+            ),
+        ];
+
+        for (pattern, string, expected) in cases {
+            assert_eq!(
+                matches(pattern.as_bytes(), string.as_bytes()),
+                *expected,
+                "{pattern} against {string}"
+            );
+        }
+    }
+
+    // backtracking every * used to take seconds here while holding the store lock
+    #[test]
+    fn matches_many_stars_quickly() {
+        let key = "a".repeat(50);
+        let started = std::time::Instant::now();
+
+        assert!(!matches(b"*a*a*a*a*a*a*a*a*b", key.as_bytes()));
+        assert!(matches(b"*a*a*a*a*a*a*a*a*", key.as_bytes()));
+        assert!(!matches(
+            "*a".repeat(30).as_bytes(),
+            "a".repeat(20).as_bytes()
+        ));
+        assert!(started.elapsed() < std::time::Duration::from_millis(100));
+    }
+
+    #[test]
+    fn matches_escapes() {
+        let cases: &[(&str, &str, bool)] = &[
+            (r"what\?", "what?", true),
+            (r"what\?", "whatx", false),
+            (r"\[tag]", "[tag]", true),
+            (r"\[tag]", "t", false),
+            (r"back\\slash", r"back\slash", true),
+            (r"back\\slash", "backslash", false),
+            // escapes work inside a class too
+            (r"[\]x]", "]", true),
+            (r"[\-]", "-", true),
+            (r"[\-]", "a", false),
+            // a trailing backslash has nothing to escape and matches itself
+            (r"end\", r"end\", true),
+            (r"end\", "end", false),
+        ];
+
+        for (pattern, string, expected) in cases {
+            assert_eq!(
+                matches(pattern.as_bytes(), string.as_bytes()),
+                *expected,
+                "{pattern} against {string}"
+            );
+        }
+    }
+}
@@ -1,7 +1,22 @@
+mod crc64;
+mod glob;
 mod storage;
 
-use crate::storage::{add as storage_add, get as storage_get, CommandData};
-use std::borrow::ToOwned;
+use crate::storage::{
+    add as storage_add, add_if as storage_add_if, add_if_absent as storage_add_if_absent,
+    add_many as storage_add_many, add_many_if_absent as storage_add_many_if_absent,
+    append as storage_append, copy as storage_copy, dump as storage_dump, exists as storage_exists,
+    flush_all as storage_flush_all, flush_db as storage_flush_db, get as storage_get,
+    get_and_remove as storage_get_and_remove, get_and_set as storage_get_and_set,
+    get_and_set_expiry as storage_get_and_set_expiry, get_many as storage_get_many,
+    increment_by as storage_increment_by, increment_by_float as storage_increment_by_float,
+    keys as storage_keys, live_count as storage_live_count, move_key as storage_move_key,
+    parse_int as storage_parse_int, peek as storage_peek, random_key as storage_random_key,
+    remove as storage_remove, rename as storage_rename, restore as storage_restore,
+    scan as storage_scan, set_expiry_if as storage_set_expiry_if,
+    set_lfu_decay_minutes as storage_set_lfu_decay_minutes, set_range as storage_set_range,
+    touch as storage_touch, unlink as storage_unlink, CommandData, DATABASES,
+};
 use std::cmp::PartialEq;
 use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::net::{TcpListener, TcpStream};
@@ -9,15 +24,21 @@ use std::str;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum RedisCommandError {
-    #[error("invalid Redis command")]
+    #[error("unknown command '{0}'")]
     Invalid(String),
     #[error("unknown command")]
     Unknown,
+    #[error("syntax error")]
+    Syntax,
+    #[error("value is not an integer or out of range")]
+    NotInteger,
+    #[error("invalid expire time in '{0}' command")]
+    InvalidExpireTime(String),
 }
 
 #[derive(Error, Debug)]
@@ -34,6 +55,57 @@ enum RedisCommand {
     Echo,
     Set,
     Get,
+    Del,
+    Exists,
+    Incr,
+    Decr,
+    Expire,
+    PExpire,
+    IncrBy,
+    DecrBy,
+    IncrByFloat,
+    Ttl,
+    PTtl,
+    Append,
+    Persist,
+    Strlen,
+    ExpireAt,
+    PExpireAt,
+    ExpireTime,
+    PExpireTime,
+    MSet,
+    MGet,
+    SetNx,
+    GetSet,
+    SetEx,
+    PSetEx,
+    GetDel,
+    GetEx,
+    SetRange,
+    GetRange,
+    Type,
+    Rename,
+    RenameNx,
+    Keys,
+    MSetNx,
+    Scan,
+    RandomKey,
+    Copy,
+    Move,
+    Object,
+    ObjectEncoding,
+    ObjectIdleTime,
+    ObjectRefCount,
+    ObjectFreq,
+    Dump,
+    Restore,
+    FlushDb,
+    Touch,
+    FlushAll,
+    Unlink,
+    DbSize,
+    Select,
+    Sort,
 }
 
 impl FromStr for RedisCommand {
@@ -45,6 +117,53 @@ impl FromStr for RedisCommand {
             "echo" => Ok(RedisCommand::Echo),
             "set" => Ok(RedisCommand::Set),
             "get" => Ok(RedisCommand::Get),
+            "del" => Ok(RedisCommand::Del),
+            "exists" => Ok(RedisCommand::Exists),
+            "incr" => Ok(RedisCommand::Incr),
+            "decr" => Ok(RedisCommand::Decr),
+            "expire" => Ok(RedisCommand::Expire),
+            "pexpire" => Ok(RedisCommand::PExpire),
+            "incrby" => Ok(RedisCommand::IncrBy),
+            "decrby" => Ok(RedisCommand::DecrBy),
+            "incrbyfloat" => Ok(RedisCommand::IncrByFloat),
+            "ttl" => Ok(RedisCommand::Ttl),
+            "pttl" => Ok(RedisCommand::PTtl),
+            "append" => Ok(RedisCommand::Append),
+            "persist" => Ok(RedisCommand::Persist),
+            "strlen" => Ok(RedisCommand::Strlen),
+            "expireat" => Ok(RedisCommand::ExpireAt),
+            "pexpireat" => Ok(RedisCommand::PExpireAt),
+            "expiretime" => Ok(RedisCommand::ExpireTime),
+            "pexpiretime" => Ok(RedisCommand::PExpireTime),
+            "mset" => Ok(RedisCommand::MSet),
+            "mget" => Ok(RedisCommand::MGet),
+            "setnx" => Ok(RedisCommand::SetNx),
+            "getset" => Ok(RedisCommand::GetSet),
+            "setex" => Ok(RedisCommand::SetEx),
+            "psetex" => Ok(RedisCommand::PSetEx),
+            "getdel" => Ok(RedisCommand::GetDel),
+            "getex" => Ok(RedisCommand::GetEx),
+            "setrange" => Ok(RedisCommand::SetRange),
+            "getrange" => Ok(RedisCommand::GetRange),
+            "type" => Ok(RedisCommand::Type),
+            "rename" => Ok(RedisCommand::Rename),
+            "renamenx" => Ok(RedisCommand::RenameNx),
+            "keys" => Ok(RedisCommand::Keys),
+            "msetnx" => Ok(RedisCommand::MSetNx),
+            "scan" => Ok(RedisCommand::Scan),
+            "randomkey" => Ok(RedisCommand::RandomKey),
+            "copy" => Ok(RedisCommand::Copy),
+            "move" => Ok(RedisCommand::Move),
+            "object" => Ok(RedisCommand::Object),
+            "dump" => Ok(RedisCommand::Dump),
+            "restore" => Ok(RedisCommand::Restore),
+            "flushdb" => Ok(RedisCommand::FlushDb),
+            "touch" => Ok(RedisCommand::Touch),
+            "flushall" => Ok(RedisCommand::FlushAll),
+            "unlink" => Ok(RedisCommand::Unlink),
+            "dbsize" => Ok(RedisCommand::DbSize),
+            "select" => Ok(RedisCommand::Select),
+            "sort" => Ok(RedisCommand::Sort),
             _ => Err(RedisCommandError::Invalid(s.to_string())),
         }
     }
@@ -53,60 +172,1127 @@ impl FromStr for RedisCommand {
 #[derive(Debug)]
 struct RedisCommandValue {
     command: RedisCommand,
-    param_1: Option<String>,
-    param_2: Option<String>,
+    params: Vec<String>,
     expires_for: Option<Duration>,
+    nx: bool,
+    xx: bool,
+    get_flag: bool,
+    keep_ttl: bool,
 }
 
 impl RedisCommandValue {
-    fn new(
-        command: RedisCommand,
-        param_1: Option<String>,
-        param_2: Option<String>,
-        expires_for: Option<Duration>,
-    ) -> Self {
+    fn new(command: RedisCommand, params: Vec<String>, expires_for: Option<Duration>) -> Self {
         Self {
             command,
-            param_1,
-            param_2,
+            params,
             expires_for,
+            nx: false,
+            xx: false,
+            get_flag: false,
+            keep_ttl: false,
         }
     }
 
-    fn to_response(&self) -> String {
+    // SET key value [NX | XX] [GET] [EX seconds | PX milliseconds | KEEPTTL]
+    fn parse_set_options(&mut self) -> Result<(), RedisCommandError> {
+        let mut options = self.params.iter().skip(2);
+
+        while let Some(option) = options.next() {
+            match option.to_lowercase().as_str() {
+                "nx" if !self.xx => self.nx = true,
+                "xx" if !self.nx => self.xx = true,
+                "get" => self.get_flag = true,
+                "keepttl" if self.expires_for.is_none() => self.keep_ttl = true,
+                unit @ ("ex" | "px") if self.expires_for.is_none() && !self.keep_ttl => {
+                    let expired = options.next().ok_or(RedisCommandError::Syntax)?;
+                    let expired = expired
+                        .parse::<i64>()
+                        .map_err(|_| RedisCommandError::NotInteger)?;
+
+                    let expires_for = if unit == "ex" {
+                        Duration::from_secs(expired.max(0) as u64)
+                    } else {
+                        Duration::from_millis(expired.max(0) as u64)
+                    };
+
+                    if expired <= 0 || deadline_after(SystemTime::now(), expires_for).is_none() {
+                        return Err(RedisCommandError::InvalidExpireTime("set".to_string()));
+                    }
+
+                    self.expires_for = Some(expires_for);
+                }
+                _ => return Err(RedisCommandError::Syntax),
+            }
+        }
+
+        Ok(())
+    }
+
+    // OBJECT ENCODING | IDLETIME | REFCOUNT | FREQ key, the subcommand becomes the command
+    // and an unknown one is left as OBJECT for to_response to reject
+    fn parse_object_subcommand(&mut self) {
+        let Some(subcommand) = self.params.first() else {
+            return;
+        };
+
+        self.command = match subcommand.to_lowercase().as_str() {
+            "encoding" => RedisCommand::ObjectEncoding,
+            "idletime" => RedisCommand::ObjectIdleTime,
+            "refcount" => RedisCommand::ObjectRefCount,
+            "freq" => RedisCommand::ObjectFreq,
+            _ => return,
+        };
+
+        self.params.remove(0);
+    }
+
+    fn to_response(&self, db: &mut usize) -> Vec<u8> {
         match self.command {
-            RedisCommand::Ping => "+PONG\r\n".to_owned(),
-            RedisCommand::Set => "+OK\r\n".to_owned(),
+            RedisCommand::Ping => b"+PONG\r\n".to_vec(),
+            RedisCommand::Set => {
+                let [key, value, ..] = self.params.as_slice() else {
+                    return wrong_arity("set");
+                };
+
+                let (written, previous) = match storage_add_if(
+                    *db,
+                    key.as_str(),
+                    value.as_str(),
+                    self.expires_for
+                        .map(|expires_for| SystemTime::now() + expires_for),
+                    self.nx,
+                    self.xx,
+                    self.keep_ttl,
+                ) {
+                    Ok(outcome) => outcome,
+                    Err(e) => return format!("-ERR {e}\r\n").into_bytes(),
+                };
+
+                // with GET the previous value is the reply whether or not NX/XX let the write happen
+                if self.get_flag {
+                    return bulk_string(previous.as_ref().map(|cd| cd.value.as_slice()));
+                }
+
+                if !written {
+                    return b"$-1\r\n".to_vec();
+                }
+
+                b"+OK\r\n".to_vec()
+            }
             RedisCommand::Get => {
-                if let Some(cd) = storage_get(self.param_2.clone().unwrap().as_str()) {
+                let [key] = self.params.as_slice() else {
+                    return wrong_arity("get");
+                };
+
+                if let Some(cd) = storage_get(*db, key.as_str()) {
                     println!("CD: {:?}", &cd.clone());
                     if filter_expired(&cd).is_some() {
-                        let len = cd.value.len();
-                        return format!("${len}\r\n{}\r\n", cd.value);
+                        return bulk_string(Some(cd.value.as_slice()));
                     }
                 }
 
-                "$-1\r\n".to_string()
+                b"$-1\r\n".to_vec()
             }
             RedisCommand::Echo => {
-                let len = self.param_2.clone().unwrap_or("".to_string()).len();
-                format!("${len}\r\n{}\r\n", self.param_2.as_ref().unwrap())
+                let [message] = self.params.as_slice() else {
+                    return wrong_arity("echo");
+                };
+
+                bulk_string(Some(message.as_bytes()))
+            }
+            RedisCommand::Del => {
+                if self.params.is_empty() {
+                    return wrong_arity("del");
+                }
+
+                let mut deleted = 0;
+
+                for key in &self.params {
+                    match storage_remove(*db, key.as_str()) {
+                        Ok(removed) => deleted += removed as usize,
+                        Err(e) => return format!("-ERR {e}\r\n").into_bytes(),
+                    }
+                }
+
+                format!(":{deleted}\r\n").into_bytes()
+            }
+            RedisCommand::Unlink => {
+                if self.params.is_empty() {
+                    return wrong_arity("unlink");
+                }
+
+                format!(":{}\r\n", storage_unlink(*db, &self.params)).into_bytes()
+            }
+            RedisCommand::Exists => {
+                if self.params.is_empty() {
+                    return wrong_arity("exists");
+                }
+
+                let existing = self
+                    .params
+                    .iter()
+                    .filter(|key| storage_exists(*db, key.as_str()))
+                    .count();
+
+                format!(":{existing}\r\n").into_bytes()
+            }
+            RedisCommand::Touch => {
+                if self.params.is_empty() {
+                    return wrong_arity("touch");
+                }
+
+                format!(":{}\r\n", storage_touch(*db, &self.params)).into_bytes()
+            }
+            RedisCommand::Incr => {
+                if self.params.len() != 1 {
+                    return wrong_arity("incr");
+                }
+
+                increment_by(*db, self.params[0].as_str(), 1)
+            }
+            RedisCommand::Decr => {
+                if self.params.len() != 1 {
+                    return wrong_arity("decr");
+                }
+
+                increment_by(*db, self.params[0].as_str(), -1)
+            }
+            RedisCommand::IncrBy | RedisCommand::DecrBy => {
+                let increase = self.command == RedisCommand::IncrBy;
+
+                let [key, delta] = self.params.as_slice() else {
+                    return wrong_arity(if increase { "incrby" } else { "decrby" });
+                };
+
+                let delta = match storage_parse_int(delta.as_bytes()) {
+                    Some(delta) if increase => delta,
+                    Some(delta) => match delta.checked_neg() {
+                        Some(delta) => delta,
+                        None => return OVERFLOW_ERROR.into(),
+                    },
+                    None => return NOT_INTEGER_ERROR.into(),
+                };
+
+                increment_by(*db, key.as_str(), delta)
+            }
+            RedisCommand::IncrByFloat => {
+                let [key, delta] = self.params.as_slice() else {
+                    return wrong_arity("incrbyfloat");
+                };
+
+                let Ok(delta) = delta.parse::<f64>() else {
+                    return NOT_FLOAT_ERROR.into();
+                };
+
+                increment_by_float(*db, key.as_str(), delta)
+            }
+            RedisCommand::Ttl | RedisCommand::PTtl => {
+                let in_seconds = self.command == RedisCommand::Ttl;
+
+                let [key] = self.params.as_slice() else {
+                    return wrong_arity(if in_seconds { "ttl" } else { "pttl" });
+                };
+
+                let ttl = match storage_peek(*db, key.as_str())
+                    .filter(|cd| filter_expired(cd).is_some())
+                {
+                    None => -2,
+                    Some(cd) => match cd.remaining_ttl() {
+                        None => -1,
+                        Some(ttl) if in_seconds => rounded_seconds(ttl),
+                        Some(ttl) => saturating_i64(ttl.as_millis()),
+                    },
+                };
+
+                format!(":{ttl}\r\n").into_bytes()
+            }
+            RedisCommand::Append => {
+                let [key, suffix] = self.params.as_slice() else {
+                    return wrong_arity("append");
+                };
+
+                match storage_append(*db, key.as_str(), suffix.as_str()) {
+                    Ok(len) => format!(":{len}\r\n").into_bytes(),
+                    Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+                }
+            }
+            RedisCommand::ExpireTime | RedisCommand::PExpireTime => {
+                let in_seconds = self.command == RedisCommand::ExpireTime;
+
+                let [key] = self.params.as_slice() else {
+                    return wrong_arity(if in_seconds {
+                        "expiretime"
+                    } else {
+                        "pexpiretime"
+                    });
+                };
+
+                let expire_time = match storage_peek(*db, key.as_str())
+                    .filter(|cd| filter_expired(cd).is_some())
+                {
+                    None => -2,
+                    Some(cd) => match cd.expires_at {
+                        None => -1,
+                        Some(expires_at) => {
+                            let since_epoch = expires_at
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap_or(Duration::new(0, 0));
+
+                            if in_seconds {
+                                saturating_i64(u128::from(since_epoch.as_secs()))
+                            } else {
+                                saturating_i64(since_epoch.as_millis())
+                            }
+                        }
+                    },
+                };
+
+                format!(":{expire_time}\r\n").into_bytes()
+            }
+            RedisCommand::MSet | RedisCommand::MSetNx => {
+                let nx = self.command == RedisCommand::MSetNx;
+                let name = if nx { "msetnx" } else { "mset" };
+
+                if self.params.is_empty() || !self.params.len().is_multiple_of(2) {
+                    return wrong_arity(name);
+                }
+
+                let pairs = self
+                    .params
+                    .chunks(2)
+                    .map(|pair| (pair[0].as_str(), pair[1].as_str()))
+                    .collect::<Vec<(&str, &str)>>();
+
+                if !nx {
+                    return match storage_add_many(*db, &pairs) {
+                        Ok(_) => b"+OK\r\n".to_vec(),
+                        Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+                    };
+                }
+
+                match storage_add_many_if_absent(*db, &pairs) {
+                    Ok(written) => format!(":{}\r\n", written as u8).into_bytes(),
+                    Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+                }
+            }
+            RedisCommand::MGet => {
+                if self.params.is_empty() {
+                    return wrong_arity("mget");
+                }
+
+                let values = storage_get_many(*db, &self.params)
+                    .iter()
+                    .map(|cd| bulk_string(cd.as_ref().map(|cd| cd.value.as_slice())))
+                    .collect::<Vec<Vec<u8>>>();
+
+                array(values)
+            }
+            RedisCommand::SetNx => {
+                let [key, value] = self.params.as_slice() else {
+                    return wrong_arity("setnx");
+                };
+
+                match storage_add_if_absent(*db, key.as_str(), value.as_str()) {
+                    Ok(added) => format!(":{}\r\n", added as u8).into_bytes(),
+                    Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+                }
+            }
+            RedisCommand::GetSet => {
+                let [key, value] = self.params.as_slice() else {
+                    return wrong_arity("getset");
+                };
+
+                match storage_get_and_set(*db, key.as_str(), value.as_str()) {
+                    Ok(previous) => bulk_string(previous.as_ref().map(|cd| cd.value.as_slice())),
+                    Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+                }
+            }
+            RedisCommand::SetEx | RedisCommand::PSetEx => {
+                let in_seconds = self.command == RedisCommand::SetEx;
+                let name = if in_seconds { "setex" } else { "psetex" };
+
+                let [key, timeout, value] = self.params.as_slice() else {
+                    return wrong_arity(name);
+                };
+
+                let Ok(timeout) = timeout.parse::<i64>() else {
+                    return NOT_INTEGER_ERROR.into();
+                };
+
+                if timeout <= 0 {
+                    return invalid_expire_time(name);
+                }
+
+                let expires_for = if in_seconds {
+                    Duration::from_secs(timeout as u64)
+                } else {
+                    Duration::from_millis(timeout as u64)
+                };
+
+                let Some(expires_at) = deadline_after(SystemTime::now(), expires_for) else {
+                    return invalid_expire_time(name);
+                };
+
+                match storage_add(*db, key.as_str(), value.as_str(), Some(expires_at)) {
+                    Ok(_) => b"+OK\r\n".to_vec(),
+                    Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+                }
+            }
+            RedisCommand::GetDel => {
+                let [key] = self.params.as_slice() else {
+                    return wrong_arity("getdel");
+                };
+
+                match storage_get_and_remove(*db, key.as_str()) {
+                    Ok(removed) => bulk_string(removed.as_ref().map(|cd| cd.value.as_slice())),
+                    Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+                }
+            }
+            RedisCommand::GetEx => {
+                let Some((key, options)) = self.params.split_first() else {
+                    return wrong_arity("getex");
+                };
+
+                let current = match options {
+                    [] => storage_get(*db, key.as_str()).filter(|cd| filter_expired(cd).is_some()),
+                    [persist] if persist.eq_ignore_ascii_case("persist") => {
+                        match storage_get_and_set_expiry(*db, key.as_str(), None) {
+                            Ok(current) => current,
+                            Err(e) => return format!("-ERR {e}\r\n").into_bytes(),
+                        }
+                    }
+                    [unit, amount] => {
+                        let expires_at = match parse_expires_at(unit, amount, "getex") {
+                            Ok(expires_at) => expires_at,
+                            Err(e) => return e,
+                        };
+
+                        match storage_get_and_set_expiry(*db, key.as_str(), Some(expires_at)) {
+                            Ok(current) => current,
+                            Err(e) => return format!("-ERR {e}\r\n").into_bytes(),
+                        }
+                    }
+                    _ => return SYNTAX_ERROR.into(),
+                };
+
+                bulk_string(current.as_ref().map(|cd| cd.value.as_slice()))
+            }
+            RedisCommand::SetRange => {
+                let [key, offset, value] = self.params.as_slice() else {
+                    return wrong_arity("setrange");
+                };
+
+                let Ok(offset) = offset.parse::<i64>() else {
+                    return NOT_INTEGER_ERROR.into();
+                };
+
+                if offset < 0 {
+                    return b"-ERR offset is out of range\r\n".to_vec();
+                }
+
+                // an empty value never grows the string, so only a real write is capped
+                if !value.is_empty() && offset as u64 + value.len() as u64 > MAX_STRING_LEN {
+                    return b"-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n"
+                        .to_vec();
+                }
+
+                match storage_set_range(*db, key.as_str(), offset as usize, value.as_str()) {
+                    Ok(len) => format!(":{len}\r\n").into_bytes(),
+                    Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+                }
+            }
+            RedisCommand::GetRange => {
+                let [key, start, end] = self.params.as_slice() else {
+                    return wrong_arity("getrange");
+                };
+
+                let (Ok(start), Ok(end)) = (start.parse::<i64>(), end.parse::<i64>()) else {
+                    return NOT_INTEGER_ERROR.into();
+                };
+
+                let value = storage_get(*db, key.as_str())
+                    .filter(|cd| filter_expired(cd).is_some())
+                    .map_or(vec![], |cd| cd.value);
+
+                bulk_string(Some(byte_range(&value, start, end)))
+            }
+            RedisCommand::Persist => {
+                let [key] = self.params.as_slice() else {
+                    return wrong_arity("persist");
+                };
+
+                // only a key that has a TTL counts as persisted
+                match storage_set_expiry_if(*db, key.as_str(), None, |current| current.is_some()) {
+                    Ok(persisted) => format!(":{}\r\n", persisted as u8).into_bytes(),
+                    Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+                }
+            }
+            RedisCommand::Strlen => {
+                let [key] = self.params.as_slice() else {
+                    return wrong_arity("strlen");
+                };
+
+                let len = storage_get(*db, key.as_str())
+                    .filter(|cd| filter_expired(cd).is_some())
+                    .map_or(0, |cd| cd.value.len());
+
+                format!(":{len}\r\n").into_bytes()
+            }
+            RedisCommand::Keys => {
+                let [pattern] = self.params.as_slice() else {
+                    return wrong_arity("keys");
+                };
+
+                let keys = storage_keys(*db, pattern.as_str())
+                    .iter()
+                    .map(|key| bulk_string(Some(key.as_bytes())))
+                    .collect::<Vec<Vec<u8>>>();
+
+                array(keys)
+            }
+            RedisCommand::Scan => {
+                let [cursor, options @ ..] = self.params.as_slice() else {
+                    return wrong_arity("scan");
+                };
+
+                let Ok(cursor) = cursor.parse::<u64>() else {
+                    return b"-ERR invalid cursor\r\n".to_vec();
+                };
+
+                let (match_pattern, count) = match parse_scan_options(options) {
+                    Ok(parsed) => parsed,
+                    Err(e) => return e,
+                };
+
+                let (next_cursor, keys) = storage_scan(*db, cursor, match_pattern, count);
+
+                let keys = keys
+                    .iter()
+                    .map(|key| bulk_string(Some(key.as_bytes())))
+                    .collect::<Vec<Vec<u8>>>();
+
+                array(vec![
+                    bulk_string(Some(next_cursor.to_string().as_bytes())),
+                    array(keys),
+                ])
+            }
+            RedisCommand::RandomKey => {
+                if !self.params.is_empty() {
+                    return wrong_arity("randomkey");
+                }
+
+                bulk_string(storage_random_key(*db).as_ref().map(|key| key.as_bytes()))
+            }
+            RedisCommand::DbSize => {
+                if !self.params.is_empty() {
+                    return wrong_arity("dbsize");
+                }
+
+                format!(":{}\r\n", storage_live_count(*db)).into_bytes()
+            }
+            RedisCommand::Copy => {
+                let [src, dst, options @ ..] = self.params.as_slice() else {
+                    return wrong_arity("copy");
+                };
+
+                let (dst_db, replace) = match parse_copy_options(options) {
+                    Ok(parsed) => parsed,
+                    Err(e) => return e,
+                };
+
+                let dst_db = dst_db.unwrap_or(*db);
+
+                if src == dst && dst_db == *db {
+                    return b"-ERR source and destination objects are the same\r\n".to_vec();
+                }
+
+                match storage_copy(*db, src.as_str(), dst_db, dst.as_str(), replace) {
+                    Ok(copied) => format!(":{}\r\n", copied as u8).into_bytes(),
+                    Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+                }
+            }
+            RedisCommand::Move => {
+                let [key, dst_db] = self.params.as_slice() else {
+                    return wrong_arity("move");
+                };
+
+                let dst_db = match parse_db_index(dst_db) {
+                    Ok(dst_db) => dst_db,
+                    Err(e) => return e,
+                };
+
+                if dst_db == *db {
+                    return b"-ERR source and destination objects are the same\r\n".to_vec();
+                }
+
+                match storage_move_key(*db, key.as_str(), dst_db) {
+                    Ok(moved) => format!(":{}\r\n", moved as u8).into_bytes(),
+                    Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+                }
+            }
+            RedisCommand::Sort => {
+                let [key, options @ ..] = self.params.as_slice() else {
+                    return wrong_arity("sort");
+                };
+
+                let store = match parse_sort_args(options) {
+                    Ok(store) => store,
+                    Err(e) => return e,
+                };
+
+                // only strings are stored so far, and those can't be sorted
+                if storage_peek(*db, key.as_str()).is_some_and(|cd| filter_expired(&cd).is_some()) {
+                    return WRONG_TYPE_ERROR.into();
+                }
+
+                // a missing key sorts to an empty list, STORE then leaves dst missing too
+                match store {
+                    None => array(vec![]),
+                    Some(dst) => match storage_remove(*db, dst) {
+                        Ok(_) => b":0\r\n".to_vec(),
+                        Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+                    },
+                }
+            }
+            RedisCommand::Select => {
+                let [index] = self.params.as_slice() else {
+                    return wrong_arity("select");
+                };
+
+                match parse_db_index(index) {
+                    Ok(index) => {
+                        *db = index;
+
+                        b"+OK\r\n".to_vec()
+                    }
+                    Err(e) => e,
+                }
+            }
+            // only reached when parse_object_subcommand didn't recognise the subcommand
+            RedisCommand::Object => match self.params.first() {
+                None => wrong_arity("object"),
+                Some(subcommand) => unknown_subcommand(subcommand, "object"),
+            },
+            RedisCommand::ObjectEncoding => {
+                let [key] = self.params.as_slice() else {
+                    return unknown_subcommand("encoding", "object");
+                };
+
+                let cd = storage_peek(*db, key.as_str()).filter(|cd| filter_expired(cd).is_some());
+
+                bulk_string(cd.map(|cd| cd.encoding().as_bytes()))
+            }
+            RedisCommand::ObjectIdleTime => {
+                let [key] = self.params.as_slice() else {
+                    return unknown_subcommand("idletime", "object");
+                };
+
+                match storage_peek(*db, key.as_str()).filter(|cd| filter_expired(cd).is_some()) {
+                    Some(cd) => {
+                        let idle = cd.last_accessed.elapsed().unwrap_or_default();
+
+                        format!(":{}\r\n", idle.as_secs()).into_bytes()
+                    }
+                    None => b"$-1\r\n".to_vec(),
+                }
+            }
+            RedisCommand::ObjectRefCount => {
+                let [key] = self.params.as_slice() else {
+                    return unknown_subcommand("refcount", "object");
+                };
+
+                // values are never shared between keys
+                match storage_peek(*db, key.as_str()).filter(|cd| filter_expired(cd).is_some()) {
+                    Some(_) => b":1\r\n".to_vec(),
+                    None => b"-ERR no such key\r\n".to_vec(),
+                }
+            }
+            RedisCommand::ObjectFreq => {
+                let [key] = self.params.as_slice() else {
+                    return unknown_subcommand("freq", "object");
+                };
+
+                match storage_peek(*db, key.as_str()).filter(|cd| filter_expired(cd).is_some()) {
+                    Some(cd) => format!(":{}\r\n", cd.access_frequency()).into_bytes(),
+                    None => b"-ERR no such key\r\n".to_vec(),
+                }
+            }
+            RedisCommand::Dump => {
+                let [key] = self.params.as_slice() else {
+                    return wrong_arity("dump");
+                };
+
+                bulk_string(
+                    storage_dump(*db, key.as_str())
+                        .as_ref()
+                        .map(|p| p.as_bytes()),
+                )
+            }
+            RedisCommand::Restore => {
+                let [key, ttl, payload, options @ ..] = self.params.as_slice() else {
+                    return wrong_arity("restore");
+                };
+
+                let Ok(ttl) = ttl.parse::<i64>() else {
+                    return NOT_INTEGER_ERROR.into();
+                };
+
+                if ttl < 0 {
+                    return b"-ERR Invalid TTL value, must be >= 0\r\n".to_vec();
+                }
+
+                let (replace, absolute_ttl) = match parse_restore_options(options) {
+                    Ok(parsed) => parsed,
+                    Err(e) => return e,
+                };
+
+                // 0 means no TTL, ABSTTL makes it a unix time in milliseconds
+                let ttl = Duration::from_millis(ttl as u64);
+                let expires_at = match (ttl.is_zero(), absolute_ttl) {
+                    (true, _) => None,
+                    (false, true) => deadline_after(UNIX_EPOCH, ttl),
+                    (false, false) => deadline_after(SystemTime::now(), ttl),
+                };
+
+                if !ttl.is_zero() && expires_at.is_none() {
+                    return invalid_expire_time("restore");
+                }
+
+                match storage_restore(*db, key.as_str(), payload.as_str(), expires_at, replace) {
+                    Ok(true) => b"+OK\r\n".to_vec(),
+                    Ok(false) => b"-BUSYKEY Target key name already exists.\r\n".to_vec(),
+                    Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+                }
+            }
+            RedisCommand::FlushDb | RedisCommand::FlushAll => {
+                let all = self.command == RedisCommand::FlushAll;
+
+                // ASYNC and SYNC are accepted, the store is always cleared right away
+                match self.params.as_slice() {
+                    [] => {}
+                    [mode] if ["async", "sync"].contains(&mode.to_lowercase().as_str()) => {}
+                    [_] => return SYNTAX_ERROR.into(),
+                    _ => return wrong_arity(if all { "flushall" } else { "flushdb" }),
+                }
+
+                let flushed = if all {
+                    storage_flush_all()
+                } else {
+                    storage_flush_db(*db)
+                };
+
+                match flushed {
+                    Ok(()) => b"+OK\r\n".to_vec(),
+                    Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+                }
+            }
+            RedisCommand::Type => {
+                let [key] = self.params.as_slice() else {
+                    return wrong_arity("type");
+                };
+
+                let name = storage_peek(*db, key.as_str())
+                    .filter(|cd| filter_expired(cd).is_some())
+                    .map_or("none", |cd| cd.value_type.name());
+
+                format!("+{name}\r\n").into_bytes()
+            }
+            RedisCommand::Rename | RedisCommand::RenameNx => {
+                let nx = self.command == RedisCommand::RenameNx;
+                let name = if nx { "renamenx" } else { "rename" };
+
+                let [src, dst] = self.params.as_slice() else {
+                    return wrong_arity(name);
+                };
+
+                match storage_rename(*db, src.as_str(), dst.as_str(), nx) {
+                    Ok(_) if !nx => b"+OK\r\n".to_vec(),
+                    Ok(renamed) => format!(":{}\r\n", renamed as u8).into_bytes(),
+                    Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+                }
+            }
+            RedisCommand::Expire | RedisCommand::PExpire => {
+                let in_seconds = self.command == RedisCommand::Expire;
+                let name = if in_seconds { "expire" } else { "pexpire" };
+
+                let [key, timeout, options @ ..] = self.params.as_slice() else {
+                    return wrong_arity(name);
+                };
+
+                let Ok(timeout) = timeout.parse::<i64>() else {
+                    return NOT_INTEGER_ERROR.into();
+                };
+
+                let condition = match ExpireCondition::parse(options) {
+                    Ok(condition) => condition,
+                    Err(e) => return e,
+                };
+
+                let expires_for = if in_seconds {
+                    Duration::from_secs(timeout.unsigned_abs())
+                } else {
+                    Duration::from_millis(timeout.unsigned_abs())
+                };
+
+                // zero or negative timeouts land in the past, so expire_key deletes the key
+                let expires_at = if timeout > 0 {
+                    deadline_after(SystemTime::now(), expires_for)
+                } else {
+                    Some(
+                        SystemTime::now()
+                            .checked_sub(expires_for)
+                            .unwrap_or(UNIX_EPOCH),
+                    )
+                };
+
+                match expires_at {
+                    Some(expires_at) => expire_key(*db, key.as_str(), expires_at, &condition),
+                    None => invalid_expire_time(name),
+                }
+            }
+            RedisCommand::ExpireAt | RedisCommand::PExpireAt => {
+                let in_seconds = self.command == RedisCommand::ExpireAt;
+                let name = if in_seconds { "expireat" } else { "pexpireat" };
+
+                let [key, timestamp, options @ ..] = self.params.as_slice() else {
+                    return wrong_arity(name);
+                };
+
+                let Ok(timestamp) = timestamp.parse::<i64>() else {
+                    return NOT_INTEGER_ERROR.into();
+                };
+
+                let condition = match ExpireCondition::parse(options) {
+                    Ok(condition) => condition,
+                    Err(e) => return e,
+                };
+
+                // a timestamp in the past deletes the key, one before the epoch
+                // or past i64 milliseconds is rejected
+                if timestamp < 0 || (in_seconds && timestamp > i64::MAX / 1000) {
+                    return invalid_expire_time(name);
+                }
+
+                let since_epoch = if in_seconds {
+                    Duration::from_secs(timestamp as u64)
+                } else {
+                    Duration::from_millis(timestamp as u64)
+                };
+
+                match deadline_after(UNIX_EPOCH, since_epoch) {
+                    Some(expires_at) => expire_key(*db, key.as_str(), expires_at, &condition),
+                    None => invalid_expire_time(name),
+                }
             }
         }
     }
 }
 
-fn filter_expired(data: &CommandData) -> Option<&CommandData> {
-    match data.expires_for {
-        None => Some(data),
-        Some(expiration) => {
-            if data.created_at.elapsed().unwrap_or(Duration::new(0, 0)) > expiration {
-                return None;
+// a deadline that already passed deletes the key right away
+fn expire_key(
+    db: usize,
+    key: &str,
+    expires_at: SystemTime,
+    condition: &ExpireCondition,
+) -> Vec<u8> {
+    match storage_set_expiry_if(db, key, Some(expires_at), |current| {
+        condition.allows(current, expires_at)
+    }) {
+        Ok(updated) => format!(":{}\r\n", updated as u8).into_bytes(),
+        Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+    }
+}
+
+const SYNTAX_ERROR: &str = "-ERR syntax error\r\n";
+
+// EX seconds | PX milliseconds | EXAT unix-seconds | PXAT unix-milliseconds
+fn parse_expires_at(unit: &str, amount: &str, command: &str) -> Result<SystemTime, Vec<u8>> {
+    let unit = unit.to_lowercase();
+
+    if !["ex", "px", "exat", "pxat"].contains(&unit.as_str()) {
+        return Err(SYNTAX_ERROR.into());
+    }
+
+    let Ok(amount) = amount.parse::<i64>() else {
+        return Err(NOT_INTEGER_ERROR.into());
+    };
+
+    if amount <= 0 {
+        return Err(invalid_expire_time(command));
+    }
+
+    let expires_at = match unit.as_str() {
+        "ex" => deadline_after(SystemTime::now(), Duration::from_secs(amount as u64)),
+        "px" => deadline_after(SystemTime::now(), Duration::from_millis(amount as u64)),
+        "exat" => deadline_after(UNIX_EPOCH, Duration::from_secs(amount as u64)),
+        _ => deadline_after(UNIX_EPOCH, Duration::from_millis(amount as u64)),
+    };
+
+    expires_at.ok_or_else(|| invalid_expire_time(command))
+}
+
+// Redis keeps deadlines as unix time in milliseconds in an i64,
+// so one past that is rejected like a SystemTime overflow
+fn deadline_after(base: SystemTime, offset: Duration) -> Option<SystemTime> {
+    base.checked_add(offset).filter(|deadline| {
+        deadline
+            .duration_since(UNIX_EPOCH)
+            .map_or(true, |since_epoch| {
+                i64::try_from(since_epoch.as_millis()).is_ok()
+            })
+    })
+}
+
+// milliseconds and seconds reported back to clients, saturating instead of wrapping
+fn saturating_i64(value: u128) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+// rounded to the nearest second the same way Redis does for TTL
+fn rounded_seconds(ttl: Duration) -> i64 {
+    saturating_i64((ttl.as_millis() + 500) / 1000)
+}
+
+fn invalid_expire_time(command: &str) -> Vec<u8> {
+    format!("-ERR invalid expire time in '{command}' command\r\n").into_bytes()
+}
+
+// NX - set only when the key has no expiry
+// XX - set only when the key already has an expiry
+// GT - set only when the new expiry is greater than the current one
+// LT - set only when the new expiry is less than the current one
+#[derive(Default, Debug)]
+struct ExpireCondition {
+    nx: bool,
+    xx: bool,
+    gt: bool,
+    lt: bool,
+}
+
+impl ExpireCondition {
+    fn parse(options: &[String]) -> Result<Self, Vec<u8>> {
+        let mut condition = Self::default();
+
+        for option in options {
+            match option.to_lowercase().as_str() {
+                "nx" => condition.nx = true,
+                "xx" => condition.xx = true,
+                "gt" => condition.gt = true,
+                "lt" => condition.lt = true,
+                _ => return Err(format!("-ERR Unsupported option {option}\r\n").into_bytes()),
+            }
+        }
+
+        if condition.nx && (condition.xx || condition.gt || condition.lt) {
+            return Err(
+                "-ERR NX and XX, GT or LT options at the same time are not compatible\r\n".into(),
+            );
+        }
+
+        if condition.gt && condition.lt {
+            return Err("-ERR GT and LT options at the same time are not compatible\r\n".into());
+        }
+
+        Ok(condition)
+    }
+
+    // a key without expiry is treated as having an infinite TTL for GT and LT
+    fn allows(&self, current: Option<SystemTime>, new: SystemTime) -> bool {
+        match current {
+            None => !(self.xx || self.gt),
+            Some(current) => !self.nx && (!self.gt || new > current) && (!self.lt || new < current),
+        }
+    }
+}
+
+// same 512MB limit Redis puts on a single string value
+const MAX_STRING_LEN: u64 = 512 * 1024 * 1024;
+
+const NOT_INTEGER_ERROR: &str = "-ERR value is not an integer or out of range\r\n";
+const OVERFLOW_ERROR: &str = "-ERR increment or decrement would overflow\r\n";
+
+fn increment_by(db: usize, key: &str, delta: i64) -> Vec<u8> {
+    match storage_increment_by(db, key, delta) {
+        Ok(value) => format!(":{value}\r\n").into_bytes(),
+        Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+    }
+}
+
+const NOT_FLOAT_ERROR: &str = "-ERR value is not a valid float\r\n";
+
+fn increment_by_float(db: usize, key: &str, delta: f64) -> Vec<u8> {
+    match storage_increment_by_float(db, key, delta) {
+        Ok(value) => bulk_string(Some(value.as_bytes())),
+        Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+    }
+}
+
+// [DB destination-db] [REPLACE], no DB means the connection's current one
+fn parse_copy_options(options: &[String]) -> Result<(Option<usize>, bool), Vec<u8>> {
+    let mut dst_db = None;
+    let mut replace = false;
+    let mut options = options.iter();
+
+    while let Some(option) = options.next() {
+        match option.to_lowercase().as_str() {
+            "replace" => replace = true,
+            "db" => {
+                let Some(db) = options.next() else {
+                    return Err(SYNTAX_ERROR.into());
+                };
+
+                dst_db = Some(parse_db_index(db)?);
             }
+            _ => return Err(SYNTAX_ERROR.into()),
+        }
+    }
+
+    Ok((dst_db, replace))
+}
+
+fn parse_db_index(index: &str) -> Result<usize, Vec<u8>> {
+    match index.parse::<i64>() {
+        Ok(index) if (0..DATABASES as i64).contains(&index) => Ok(index as usize),
+        Ok(_) => Err(b"-ERR DB index is out of range\r\n".to_vec()),
+        Err(_) => Err(NOT_INTEGER_ERROR.into()),
+    }
+}
+
+// [REPLACE] [ABSTTL]
+fn parse_restore_options(options: &[String]) -> Result<(bool, bool), Vec<u8>> {
+    let mut replace = false;
+    let mut absolute_ttl = false;
 
-            Some(data)
+    for option in options {
+        match option.to_lowercase().as_str() {
+            "replace" => replace = true,
+            "absttl" => absolute_ttl = true,
+            _ => return Err(SYNTAX_ERROR.into()),
         }
     }
+
+    Ok((replace, absolute_ttl))
+}
+
+const WRONG_TYPE_ERROR: &str =
+    "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+
+// [BY pattern] [LIMIT offset count] [GET pattern ...] [ASC | DESC] [ALPHA] [STORE destination],
+// everything is validated but only the STORE destination matters until lists exist
+fn parse_sort_args(options: &[String]) -> Result<Option<&str>, Vec<u8>> {
+    let mut store = None;
+    let mut options = options.iter();
+
+    while let Some(option) = options.next() {
+        match option.to_lowercase().as_str() {
+            "asc" | "desc" | "alpha" => {}
+            "by" | "get" => {
+                options
+                    .next()
+                    .ok_or_else(|| SYNTAX_ERROR.as_bytes().to_vec())?;
+            }
+            "store" => {
+                let dst = options
+                    .next()
+                    .ok_or_else(|| SYNTAX_ERROR.as_bytes().to_vec())?;
+                store = Some(dst.as_str());
+            }
+            "limit" => {
+                let (Some(offset), Some(count)) = (options.next(), options.next()) else {
+                    return Err(SYNTAX_ERROR.into());
+                };
+
+                if offset.parse::<i64>().is_err() || count.parse::<i64>().is_err() {
+                    return Err(NOT_INTEGER_ERROR.into());
+                }
+            }
+            _ => return Err(SYNTAX_ERROR.into()),
+        }
+    }
+
+    Ok(store)
+}
+
+// [MATCH pattern] [COUNT count] in any order, COUNT defaults to 10 like in Redis
+fn parse_scan_options(options: &[String]) -> Result<(Option<&str>, usize), Vec<u8>> {
+    let mut match_pattern = None;
+    let mut count = 10;
+    let mut options = options.iter();
+
+    while let Some(option) = options.next() {
+        let Some(value) = options.next() else {
+            return Err(SYNTAX_ERROR.into());
+        };
+
+        match option.to_lowercase().as_str() {
+            "match" => match_pattern = Some(value.as_str()),
+            "count" => {
+                count = value
+                    .parse::<usize>()
+                    .map_err(|_| NOT_INTEGER_ERROR.as_bytes().to_vec())?;
+
+                if count == 0 {
+                    return Err(SYNTAX_ERROR.into());
+                }
+            }
+            _ => return Err(SYNTAX_ERROR.into()),
+        }
+    }
+
+    Ok((match_pattern, count))
+}
+
+// inclusive byte indexes, negative ones count from the end and both get clamped
+fn byte_range(value: &[u8], start: i64, end: i64) -> &[u8] {
+    let len = value.len() as i64;
+
+    if len == 0 {
+        return &[];
+    }
+
+    let start = if start < 0 { start + len } else { start }.max(0);
+    let end = if end < 0 { end + len } else { end }.clamp(0, len - 1);
+
+    if start > end {
+        return &[];
+    }
+
+    &value[start as usize..=end as usize]
+}
+
+fn bulk_string(value: Option<&[u8]>) -> Vec<u8> {
+    match value {
+        Some(value) => [format!("${}\r\n", value.len()).as_bytes(), value, b"\r\n"].concat(),
+        None => b"$-1\r\n".to_vec(),
+    }
+}
+
+fn array(elements: Vec<Vec<u8>>) -> Vec<u8> {
+    [
+        format!("*{}\r\n", elements.len()).into_bytes(),
+        elements.concat(),
+    ]
+    .concat()
+}
+
+fn wrong_arity(command: &str) -> Vec<u8> {
+    format!("-ERR wrong number of arguments for '{command}' command\r\n").into_bytes()
+}
+
+fn unknown_subcommand(subcommand: &str, command: &str) -> Vec<u8> {
+    format!(
+        "-ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try {} HELP.\r\n",
+        command.to_uppercase()
+    )
+    .into_bytes()
+}
+
+fn filter_expired(data: &CommandData) -> Option<&CommandData> {
+    if data.is_expired() {
+        return None;
+    }
+
+    Some(data)
 }
 
 fn handle_stream_process(stream_rcp: Arc<Mutex<TcpStream>>) {
@@ -114,6 +1300,8 @@ fn handle_stream_process(stream_rcp: Arc<Mutex<TcpStream>>) {
     let reader = BufReader::new(&*stream_locked);
 
     let mut command_queue: Vec<String> = vec![];
+    // selected by SELECT, every connection starts on database 0
+    let mut db = 0;
 
     for l in reader.lines() {
         command_queue.push(l.unwrap().to_string());
@@ -121,20 +1309,14 @@ fn handle_stream_process(stream_rcp: Arc<Mutex<TcpStream>>) {
         if let Some(_command_value) = parse_redis_protocol(&command_queue) {
             command_queue.clear();
 
-            if let Some(key) = &_command_value.param_1 {
-                storage_add(
-                    key.as_str(),
-                    _command_value.param_2.clone().unwrap().as_str(),
-                    _command_value.expires_for,
-                )
-                    .expect("data was saved");
-            }
+            let response = match _command_value {
+                Ok(command_value) => command_value.to_response(&mut db),
+                Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+            };
 
             let mut writer = BufWriter::new(&*stream_locked);
 
-            writer
-                .write_all(_command_value.to_response().as_bytes())
-                .expect("response was failed");
+            writer.write_all(&response).expect("response was failed");
         }
     }
 }
@@ -143,16 +1325,22 @@ fn handle_stream_process(stream_rcp: Arc<Mutex<TcpStream>>) {
 // 1
 // 2 - redis_command
 // 3
-// 4 - value
+// 4 - param
+// ...
+// 2n + 2 - param
 
 // *1\r\n$4\r\nPING\r\n
 // *2\r\n$4\r\nECHO\r\n$3\r\nhey\r\n
 // "*3\r\n$3\r\nSET\r\n$4\r\npear\r\n$6\r\norange\r\n"
 // "*5\r\n$3\r\nSET\r\n$4\r\npear\r\n$6\r\norange\r\n$2\r\npx\r\n$3\r\n100\r\n"
+// "*3\r\n$3\r\nDEL\r\n$1\r\na\r\n$1\r\nb\r\n"
 // +OK\r\n
 // $3\r\nbar\r\n
 // $-1\r\n
-fn parse_redis_protocol(command_queue: &Vec<String>) -> Option<RedisCommandValue> {
+// :1\r\n
+fn parse_redis_protocol(
+    command_queue: &[String],
+) -> Option<Result<RedisCommandValue, RedisCommandError>> {
     if command_queue.len() < 3 {
         return None;
     }
@@ -165,51 +1353,51 @@ fn parse_redis_protocol(command_queue: &Vec<String>) -> Option<RedisCommandValue
         .parse::<usize>()
         .unwrap();
 
-    match params_count {
-        1 => match command_queue.as_slice() {
-            [_, _, command] => Some(RedisCommandValue::new(
-                RedisCommand::from_str(command).unwrap(),
-                None,
-                None,
-                None,
-            )),
-            _ => None,
-        },
-        2 => match command_queue.as_slice() {
-            [_, _, command, _, value] => Some(RedisCommandValue::new(
-                RedisCommand::from_str(command).unwrap(),
-                None,
-                Some(value.to_string()),
-                None,
-            )),
-            _ => None,
-        },
-        3 => match command_queue.as_slice() {
-            [_, _, command, _, key, _, value] => Some(RedisCommandValue::new(
-                RedisCommand::from_str(command).unwrap(),
-                Some(key.to_string()),
-                Some(value.to_string()),
-                None,
-            )),
-            _ => None,
-        },
-        5 => match command_queue.as_slice() {
-            [_, _, command, _, key, _, value, _, _, _, expired    ] => {
-                println!("command_queue: {:?}", command_queue);
-                Some(RedisCommandValue::new(
-                    RedisCommand::from_str(command).unwrap(),
-                    Some(key.to_string()),
-                    Some(value.to_string()),
-                    Some(Duration::from_millis(expired.parse::<usize>().unwrap() as u64)),
-                ))
-            }
-            _ => None,
-        },
-        _ => None,
+    if command_queue.len() < params_count * 2 + 1 {
+        return None;
+    }
+
+    let mut args = command_queue.iter().skip(2).step_by(2).cloned();
+    let command = match RedisCommand::from_str(args.next().unwrap().as_str()) {
+        Ok(command) => command,
+        Err(e) => return Some(Err(e)),
+    };
+    let params = args.collect::<Vec<String>>();
+
+    let mut command_value = RedisCommandValue::new(command, params, None);
+
+    if command_value.command == RedisCommand::Set {
+        if let Err(e) = command_value.parse_set_options() {
+            return Some(Err(e));
+        }
+    }
+
+    if command_value.command == RedisCommand::Object {
+        command_value.parse_object_subcommand();
+    }
+
+    Some(Ok(command_value))
+}
+
+// redis-server style options, only --lfu-decay-time <minutes> so far
+fn apply_options(args: &[String]) {
+    let mut args = args.iter();
+
+    while let Some(option) = args.next() {
+        if option == "--lfu-decay-time" {
+            let minutes = args
+                .next()
+                .and_then(|minutes| minutes.parse::<u64>().ok())
+                .expect("--lfu-decay-time takes a number of minutes");
+
+            storage_set_lfu_decay_minutes(minutes);
+        }
     }
 }
 
 fn main() {
+    apply_options(&std::env::args().skip(1).collect::<Vec<String>>());
+
     let listener = TcpListener::bind("127.0.0.1:6379").unwrap();
     let mut handles = vec![];
 
@@ -228,3 +1416,366 @@ fn main() {
         handle.join().unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs one command on database 0 the way a connection would parse it
+    fn request(args: &[&str]) -> Vec<u8> {
+        let mut command_queue = vec![format!("*{}", args.len())];
+
+        for arg in args {
+            command_queue.push(format!("${}", arg.len()));
+            command_queue.push(arg.to_string());
+        }
+
+        match parse_redis_protocol(&command_queue).unwrap() {
+            Ok(command_value) => command_value.to_response(&mut 0),
+            Err(e) => format!("-ERR {e}\r\n").into_bytes(),
+        }
+    }
+
+    #[test]
+    fn expireat_zero_deletes_the_key() {
+        request(&["SET", "expireat:zero", "v"]);
+
+        assert_eq!(request(&["EXPIREAT", "expireat:zero", "0"]), b":1\r\n");
+        assert_eq!(request(&["EXISTS", "expireat:zero"]), b":0\r\n");
+    }
+
+    #[test]
+    fn expireat_far_future_is_rejected() {
+        let max = i64::MAX.to_string();
+        let max_seconds = (i64::MAX / 1000).to_string();
+
+        request(&["SET", "expireat:max", "v"]);
+        request(&["SET", "pexpireat:max", "v"]);
+
+        assert_eq!(
+            request(&["EXPIREAT", "expireat:max", &max]),
+            invalid_expire_time("expireat")
+        );
+        assert_eq!(request(&["EXPIRETIME", "expireat:max"]), b":-1\r\n");
+        assert_eq!(
+            request(&["EXPIREAT", "expireat:max", &max_seconds]),
+            b":1\r\n"
+        );
+        assert_eq!(
+            request(&["EXPIRETIME", "expireat:max"]),
+            format!(":{max_seconds}\r\n").into_bytes()
+        );
+
+        // the largest millisecond deadline still fits
+        assert_eq!(request(&["PEXPIREAT", "pexpireat:max", &max]), b":1\r\n");
+        assert_eq!(
+            request(&["PEXPIRETIME", "pexpireat:max"]),
+            format!(":{max}\r\n").into_bytes()
+        );
+    }
+
+    #[test]
+    fn expireat_beyond_i64_is_rejected() {
+        request(&["SET", "expireat:overflow", "v"]);
+
+        assert_eq!(
+            request(&["EXPIREAT", "expireat:overflow", "9223372036854775808"]),
+            NOT_INTEGER_ERROR.as_bytes()
+        );
+        assert_eq!(request(&["TTL", "expireat:overflow"]), b":-1\r\n");
+    }
+
+    #[test]
+    fn byte_range_indexes_bytes() {
+        // "é" is two bytes and each of "日本" is three
+        assert_eq!(byte_range("é".as_bytes(), 0, 0), b"\xc3");
+        assert_eq!(byte_range("é".as_bytes(), 1, 1), b"\xa9");
+        assert_eq!(byte_range("日本".as_bytes(), 0, 2), "日".as_bytes());
+        assert_eq!(byte_range("日本".as_bytes(), 3, 5), "本".as_bytes());
+        assert_eq!(byte_range("日本".as_bytes(), 1, 3), b"\x97\xa5\xe6");
+    }
+
+    #[test]
+    fn byte_range_counts_negative_indexes_from_the_end() {
+        assert_eq!(byte_range(b"Hello", -1, -1), b"o");
+        assert_eq!(byte_range(b"Hello", -3, -1), b"llo");
+        assert_eq!(byte_range(b"Hello", 0, -1), b"Hello");
+        assert_eq!(byte_range("日本".as_bytes(), -3, -1), "本".as_bytes());
+    }
+
+    #[test]
+    fn byte_range_clamps_out_of_range_indexes() {
+        assert_eq!(byte_range(b"Hello", -100, 100), b"Hello");
+        assert_eq!(byte_range(b"Hello", 3, 100), b"lo");
+        assert_eq!(byte_range(b"", 0, -1), b"");
+    }
+
+    #[test]
+    fn byte_range_is_empty_when_start_passes_end() {
+        assert_eq!(byte_range(b"Hello", 3, 1), b"");
+        assert_eq!(byte_range(b"Hello", 10, 20), b"");
+        assert_eq!(byte_range(b"Hello", -1, -2), b"");
+    }
+
+    #[test]
+    fn rounded_seconds_rounds_to_nearest() {
+        assert_eq!(rounded_seconds(Duration::from_millis(1500)), 2);
+        assert_eq!(rounded_seconds(Duration::from_millis(1499)), 1);
+        assert_eq!(rounded_seconds(Duration::from_millis(1000)), 1);
+        assert_eq!(rounded_seconds(Duration::from_millis(500)), 1);
+        assert_eq!(rounded_seconds(Duration::from_millis(499)), 0);
+    }
+
+    #[test]
+    fn ttl_of_px_1500_is_one_or_two_seconds() {
+        request(&["SET", "ttl:px", "v", "PX", "1500"]);
+
+        let ttl = request(&["TTL", "ttl:px"]);
+
+        assert!(ttl == b":2\r\n" || ttl == b":1\r\n", "{ttl:?}");
+    }
+
+    fn set_value(params: &[&str]) -> Result<RedisCommandValue, RedisCommandError> {
+        let params = params.iter().map(|param| param.to_string()).collect();
+        let mut command_value = RedisCommandValue::new(RedisCommand::Set, params, None);
+
+        command_value.parse_set_options().map(|_| command_value)
+    }
+
+    #[test]
+    fn parse_set_options_reads_get_with_nx_and_xx() {
+        let get = set_value(&["k", "v", "GET"]).unwrap();
+        assert!(get.get_flag && !get.nx && !get.xx);
+
+        let nx_get = set_value(&["k", "v", "nx", "get"]).unwrap();
+        assert!(nx_get.get_flag && nx_get.nx);
+
+        let xx_get = set_value(&["k", "v", "GET", "XX"]).unwrap();
+        assert!(xx_get.get_flag && xx_get.xx);
+
+        assert!(matches!(
+            set_value(&["k", "v", "NX", "XX", "GET"]),
+            Err(RedisCommandError::Syntax)
+        ));
+    }
+
+    #[test]
+    fn set_get_returns_the_previous_value() {
+        request(&["DEL", "set:get"]);
+
+        assert_eq!(request(&["SET", "set:get", "first", "GET"]), b"$-1\r\n");
+        assert_eq!(
+            request(&["SET", "set:get", "second", "GET"]),
+            b"$5\r\nfirst\r\n"
+        );
+        assert_eq!(request(&["GET", "set:get"]), b"$6\r\nsecond\r\n");
+    }
+
+    #[test]
+    fn set_nx_get_only_writes_a_missing_key() {
+        request(&["DEL", "set:nx:get"]);
+
+        assert_eq!(
+            request(&["SET", "set:nx:get", "first", "NX", "GET"]),
+            b"$-1\r\n"
+        );
+        assert_eq!(
+            request(&["SET", "set:nx:get", "second", "NX", "GET"]),
+            b"$5\r\nfirst\r\n"
+        );
+        assert_eq!(request(&["GET", "set:nx:get"]), b"$5\r\nfirst\r\n");
+    }
+
+    #[test]
+    fn set_xx_get_only_writes_an_existing_key() {
+        request(&["DEL", "set:xx:get"]);
+
+        assert_eq!(
+            request(&["SET", "set:xx:get", "first", "XX", "GET"]),
+            b"$-1\r\n"
+        );
+        assert_eq!(request(&["GET", "set:xx:get"]), b"$-1\r\n");
+
+        request(&["SET", "set:xx:get", "first"]);
+
+        assert_eq!(
+            request(&["SET", "set:xx:get", "second", "XX", "GET"]),
+            b"$5\r\nfirst\r\n"
+        );
+        assert_eq!(request(&["GET", "set:xx:get"]), b"$6\r\nsecond\r\n");
+    }
+
+    #[test]
+    fn set_ex_expires_after_ten_seconds_but_not_nine() {
+        let command_queue = "*5\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n$2\r\nEX\r\n$2\r\n10\r\n"
+            .lines()
+            .map(String::from)
+            .collect::<Vec<String>>();
+
+        let command_value = parse_redis_protocol(&command_queue).unwrap().unwrap();
+        assert_eq!(command_value.expires_for, Some(Duration::from_secs(10)));
+        assert_eq!(command_value.to_response(&mut 0), b"+OK\r\n");
+
+        let expires_at = storage_peek(0, "foo").unwrap().expires_at.unwrap();
+        assert!(expires_at > SystemTime::now() + Duration::from_secs(9));
+        assert!(expires_at <= SystemTime::now() + Duration::from_secs(10));
+    }
+
+    #[test]
+    fn restore_checks_payload_and_busykey_before_an_expired_ttl() {
+        request(&["SET", "restore:expired", "old"]);
+        let payload = String::from_utf8(request(&["DUMP", "restore:expired"])).unwrap();
+        let payload = payload.lines().nth(1).unwrap();
+
+        assert_eq!(
+            request(&["RESTORE", "restore:expired", "1", "garbage", "ABSTTL"]),
+            b"-ERR DUMP payload version or checksum are wrong\r\n"
+        );
+        assert_eq!(
+            request(&["RESTORE", "restore:expired", "1", payload, "ABSTTL"]),
+            b"-BUSYKEY Target key name already exists.\r\n"
+        );
+        assert_eq!(
+            request(&[
+                "RESTORE",
+                "restore:expired",
+                "1",
+                payload,
+                "ABSTTL",
+                "REPLACE"
+            ]),
+            b"+OK\r\n"
+        );
+        assert_eq!(request(&["EXISTS", "restore:expired"]), b":0\r\n");
+    }
+
+    #[test]
+    fn get_and_echo_check_arity() {
+        assert_eq!(
+            request(&["ECHO"]),
+            b"-ERR wrong number of arguments for 'echo' command\r\n"
+        );
+        assert_eq!(
+            request(&["GET"]),
+            b"-ERR wrong number of arguments for 'get' command\r\n"
+        );
+        assert_eq!(
+            request(&["GET", "a", "b"]),
+            b"-ERR wrong number of arguments for 'get' command\r\n"
+        );
+    }
+
+    #[test]
+    fn parse_sort_args_checks_the_grammar() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(parse_sort_args(&args(&[])), Ok(None));
+        assert_eq!(
+            parse_sort_args(&args(&[
+                "BY", "w_*", "LIMIT", "0", "5", "GET", "#", "DESC", "ALPHA"
+            ])),
+            Ok(None)
+        );
+        assert_eq!(parse_sort_args(&args(&["STORE", "dst"])), Ok(Some("dst")));
+        assert_eq!(
+            parse_sort_args(&args(&["LIMIT", "0"])),
+            Err(SYNTAX_ERROR.as_bytes().to_vec())
+        );
+        assert_eq!(
+            parse_sort_args(&args(&["LIMIT", "a", "5"])),
+            Err(NOT_INTEGER_ERROR.as_bytes().to_vec())
+        );
+        assert_eq!(
+            parse_sort_args(&args(&["SIDEWAYS"])),
+            Err(SYNTAX_ERROR.as_bytes().to_vec())
+        );
+    }
+
+    #[test]
+    fn sort_replies_empty_for_missing_and_wrongtype_for_strings() {
+        request(&["DEL", "sort:missing"]);
+        request(&["SET", "sort:string", "v"]);
+
+        assert_eq!(request(&["SORT", "sort:missing"]), b"*0\r\n");
+        assert_eq!(
+            request(&["SORT", "sort:missing", "STORE", "sort:string"]),
+            b":0\r\n"
+        );
+        assert_eq!(request(&["EXISTS", "sort:string"]), b":0\r\n");
+
+        request(&["SET", "sort:string", "v"]);
+
+        assert_eq!(
+            request(&["SORT", "sort:string", "ALPHA"]),
+            WRONG_TYPE_ERROR.as_bytes()
+        );
+    }
+
+    #[test]
+    fn relative_ttls_past_i64_milliseconds_are_rejected() {
+        request(&["SET", "ttl:huge", "v"]);
+
+        assert_eq!(
+            request(&["EXPIRE", "ttl:huge", "9000000000000000000"]),
+            invalid_expire_time("expire")
+        );
+        assert_eq!(
+            request(&["SET", "ttl:huge", "v", "EX", "9000000000000000000"]),
+            invalid_expire_time("set")
+        );
+        assert_eq!(
+            request(&["SETEX", "ttl:huge", "9000000000000000000", "v"]),
+            invalid_expire_time("setex")
+        );
+        assert_eq!(
+            request(&["GETEX", "ttl:huge", "EXAT", "9000000000000000000"]),
+            invalid_expire_time("getex")
+        );
+        assert_eq!(request(&["PTTL", "ttl:huge"]), b":-1\r\n");
+    }
+
+    #[test]
+    fn saturating_i64_never_wraps() {
+        assert_eq!(saturating_i64(42), 42);
+        assert_eq!(saturating_i64(u128::MAX), i64::MAX);
+        assert_eq!(saturating_i64(i64::MAX as u128 + 1), i64::MAX);
+    }
+
+    #[test]
+    fn metadata_commands_leave_the_access_counters_alone() {
+        request(&["SET", "peek:counters", "v", "EX", "100"]);
+        let before = storage_peek(0, "peek:counters").unwrap();
+
+        for command in ["EXISTS", "TTL", "PTTL", "EXPIRETIME", "PEXPIRETIME", "TYPE"] {
+            request(&[command, "peek:counters"]);
+        }
+
+        let after = storage_peek(0, "peek:counters").unwrap();
+
+        assert_eq!(after.lfu_freq, before.lfu_freq);
+        assert_eq!(after.last_accessed, before.last_accessed);
+
+        request(&["GET", "peek:counters"]);
+        assert!(storage_peek(0, "peek:counters").unwrap().lfu_freq > before.lfu_freq);
+    }
+
+    #[test]
+    fn increments_need_canonical_integers() {
+        request(&["SET", "incr:padded", "007"]);
+        request(&["SET", "incr:plain", "7"]);
+
+        assert_eq!(
+            request(&["INCR", "incr:padded"]),
+            NOT_INTEGER_ERROR.as_bytes()
+        );
+        assert_eq!(
+            request(&["INCRBY", "incr:plain", "+5"]),
+            NOT_INTEGER_ERROR.as_bytes()
+        );
+        assert_eq!(
+            request(&["DECRBY", "incr:plain", "05"]),
+            NOT_INTEGER_ERROR.as_bytes()
+        );
+        assert_eq!(request(&["INCRBY", "incr:plain", "-2"]), b":5\r\n");
+    }
+}
@@ -1,46 +1,273 @@
-use serde::{Deserialize, Serialize};
+use crate::crc64;
+use crate::glob;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::hash_map::{DefaultHasher, RandomState};
 use std::collections::HashMap;
 use std::fs;
-use std::fs::{OpenOptions};
-use std::io::{Write};
+use std::fs::OpenOptions;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::path::PathBuf;
+use std::str;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::thread;
 use std::time::{Duration, SystemTime};
 use thiserror::Error;
 
-const FILE_NAME: &str = "storage.json";
+#[cfg(not(test))]
+fn file_name() -> PathBuf {
+    PathBuf::from("storage.json")
+}
+
+// tests share one store, so keep their writes away from a real storage.json,
+// one file per run so nothing is left over from the previous one
+#[cfg(test)]
+fn file_name() -> PathBuf {
+    std::env::temp_dir().join(format!("storage-test-{}.json", std::process::id()))
+}
+
+// minutes of idle time that take one off the LFU counter, like lfu-decay-time in Redis,
+// 0 turns the decay off
+static LFU_DECAY_MINUTES: AtomicU64 = AtomicU64::new(1);
+
+pub fn set_lfu_decay_minutes(minutes: u64) {
+    LFU_DECAY_MINUTES.store(minutes, Ordering::Relaxed);
+}
+
+// loaded from file_name() on first access and written back after every change,
+// the lock makes each storage operation atomic across connections
+static STORE: OnceLock<Mutex<StorageData>> = OnceLock::new();
+
+// entries detached by UNLINK, dropped and persisted by a background thread
+static RECLAIM_QUEUE: OnceLock<Sender<Vec<CommandData>>> = OnceLock::new();
 
 #[derive(Error, Debug)]
 pub enum StorageError {
     #[error("Save to the Storage unsuccessful")]
     SaveUnsuccessful(String),
+    #[error("value is not an integer or out of range")]
+    NotInteger,
+    #[error("increment or decrement would overflow")]
+    Overflow,
+    #[error("value is not a valid float")]
+    NotFloat,
+    #[error("increment would produce NaN or Infinity")]
+    NotFinite,
+    #[error("no such key")]
+    NoSuchKey,
+    #[error("DUMP payload version or checksum are wrong")]
+    BadPayload,
 }
 
+// number of logical databases, the same default as Redis
+pub const DATABASES: usize = 16;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct StorageData {
+    #[serde(default)]
+    databases: Vec<HashMap<String, CommandData>>,
+    // single database layout of storage.json written before databases, moved into database 0
+    #[serde(default, skip_serializing)]
     data: HashMap<String, CommandData>,
 }
 
+impl Default for StorageData {
+    fn default() -> Self {
+        Self {
+            databases: vec![HashMap::new(); DATABASES],
+            data: HashMap::new(),
+        }
+    }
+}
+
+impl StorageData {
+    fn upgrade(mut self) -> Self {
+        self.databases.resize_with(DATABASES, HashMap::new);
+        self.databases[0].extend(self.data.drain());
+
+        for cd in self.databases.iter_mut().flat_map(HashMap::values_mut) {
+            if let Some(expires_for) = cd.expires_for.take() {
+                cd.expires_at = cd.created_at.checked_add(expires_for);
+            }
+        }
+
+        self
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct CommandData {
     pub key: String,
-    pub value: String,
+    // raw bytes rather than a String, so SETRANGE/GETRANGE can work on byte
+    // offsets without ever splitting a UTF-8 sequence into an invalid String
+    #[serde(
+        serialize_with = "serialize_value",
+        deserialize_with = "deserialize_value"
+    )]
+    pub value: Vec<u8>,
     pub created_at: SystemTime,
-    pub expires_for: Option<Duration>,
+    // absolute deadline, so EXPIREAT and friends don't depend on when the key was written
+    #[serde(default)]
+    pub expires_at: Option<SystemTime>,
+    // TTL relative to created_at written before expires_at, turned into it by upgrade
+    #[serde(default, skip_serializing)]
+    expires_for: Option<Duration>,
+    // entries saved before TYPE existed are all strings
+    #[serde(default)]
+    pub value_type: ValueType,
+    // bumped by reads in memory only, a read alone doesn't rewrite file_name()
+    #[serde(default = "SystemTime::now")]
+    pub last_accessed: SystemTime,
+    // LFU access counter as of last_accessed, use access_frequency for the decayed value
+    #[serde(default)]
+    pub lfu_freq: u8,
+}
+
+// kind of value stored under a key, as reported by TYPE
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub enum ValueType {
+    #[default]
+    String,
+}
+
+impl ValueType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValueType::String => "string",
+        }
+    }
 }
 
+impl CommandData {
+    // a fresh string entry, never accessed yet
+    pub fn new(key: &str, value: Vec<u8>, expires_at: Option<SystemTime>) -> Self {
+        Self {
+            key: key.to_string(),
+            value,
+            created_at: SystemTime::now(),
+            expires_at,
+            expires_for: None,
+            value_type: ValueType::String,
+            last_accessed: SystemTime::now(),
+            lfu_freq: 0,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            None => false,
+            Some(expires_at) => expires_at <= SystemTime::now(),
+        }
+    }
+
+    pub fn parse_value<T: FromStr>(&self) -> Option<T> {
+        str::from_utf8(&self.value).ok()?.parse::<T>().ok()
+    }
+
+    pub fn parse_int(&self) -> Option<i64> {
+        parse_int(&self.value)
+    }
+
+    // strings follow Redis: "int" for integers in canonical form, "embstr" up to 44 bytes,
+    // "raw" beyond that
+    pub fn encoding(&self) -> &'static str {
+        match self.value_type {
+            ValueType::String if self.parse_int().is_some() => "int",
+            ValueType::String if self.value.len() <= 44 => "embstr",
+            ValueType::String => "raw",
+        }
+    }
+
+    pub fn access_frequency(&self) -> u8 {
+        let decay_minutes = LFU_DECAY_MINUTES.load(Ordering::Relaxed);
+
+        if decay_minutes == 0 {
+            return self.lfu_freq;
+        }
+
+        let idle_minutes = self.last_accessed.elapsed().unwrap_or_default().as_secs() / 60;
+        let decay = idle_minutes / decay_minutes;
+
+        self.lfu_freq
+            .saturating_sub(decay.min(u8::MAX as u64) as u8)
+    }
+
+    // one more hit on top of the decayed counter, reads and writes alike
+    fn record_access(&mut self) {
+        self.lfu_freq = self.access_frequency().saturating_add(1);
+        self.last_accessed = SystemTime::now();
+    }
+
+    // a write over a live entry keeps its access history and counts as a hit
+    fn replacing(mut self, previous: Option<&CommandData>) -> Self {
+        if let Some(previous) = previous {
+            self.lfu_freq = previous.lfu_freq;
+            self.last_accessed = previous.last_accessed;
+            self.record_access();
+        }
+
+        self
+    }
+
+    pub fn remaining_ttl(&self) -> Option<Duration> {
+        self.expires_at.map(|expires_at| {
+            expires_at
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::new(0, 0))
+        })
+    }
+}
+
+// integers only in canonical form like Redis, so not "007", "+5" or " 5"
+pub fn parse_int(text: &[u8]) -> Option<i64> {
+    str::from_utf8(text)
+        .ok()?
+        .parse::<i64>()
+        .ok()
+        .filter(|n| text == n.to_string().as_bytes())
+}
+
+// text stays readable in storage.json, only binary values become a byte array
+fn serialize_value<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    match str::from_utf8(value) {
+        Ok(text) => serializer.serialize_str(text),
+        Err(_) => value.serialize(serializer),
+    }
+}
+
+// storage.json written before values became bytes keeps them as JSON strings
+fn deserialize_value<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StoredValue {
+        Text(String),
+        Bytes(Vec<u8>),
+    }
+
+    match StoredValue::deserialize(deserializer)? {
+        StoredValue::Text(text) => Ok(text.into_bytes()),
+        StoredValue::Bytes(bytes) => Ok(bytes),
+    }
+}
+
+// runs under the STORE lock, so failures are returned rather than panicking
+// and poisoning the lock for every other connection
 fn write_store(json: String) -> std::io::Result<usize> {
     let mut file = OpenOptions::new()
         .read(true)
         .write(true)
         .create(true)
         .truncate(true)
-        .open(FILE_NAME)
-        .expect("open file in r/w mode or create not exists file");
+        .open(file_name())?;
 
     file.write(json.as_ref())
 }
 
 fn read_store() -> Option<StorageData> {
-    if let Ok(_json) = fs::read_to_string(FILE_NAME) {
+    if let Ok(_json) = fs::read_to_string(file_name()) {
         let storage_data: Option<StorageData> = serde_json::from_str(_json.as_str()).unwrap();
         return storage_data;
     }
@@ -48,40 +275,1005 @@ fn read_store() -> Option<StorageData> {
     None
 }
 
-pub fn add(key: &str, value: &str, expires_for: Option<Duration>) -> Result<bool, StorageError> {
-    let mut storage_data = if let Some(_data) = read_store() {
-        _data
+fn lock_store() -> MutexGuard<'static, StorageData> {
+    STORE
+        .get_or_init(|| Mutex::new(read_store().unwrap_or_default().upgrade()))
+        .lock()
+        .unwrap()
+}
+
+fn save_store(storage_data: &StorageData) -> Result<(), StorageError> {
+    let json = serde_json::to_string(storage_data).unwrap();
+
+    match write_store(json) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(StorageError::SaveUnsuccessful(e.to_string())),
+    }
+}
+
+pub fn add(
+    db: usize,
+    key: &str,
+    value: &str,
+    expires_at: Option<SystemTime>,
+) -> Result<bool, StorageError> {
+    let mut storage_data = lock_store();
+
+    let previous = storage_data.databases[db]
+        .get(key)
+        .filter(|cd| !cd.is_expired());
+
+    let command_data =
+        CommandData::new(key, value.as_bytes().to_vec(), expires_at).replacing(previous);
+
+    storage_data.databases[db].insert(command_data.key.clone(), command_data);
+
+    save_store(&storage_data)?;
+
+    Ok(true)
+}
+
+// nx writes only over a missing or expired key, xx only over a live one,
+// keep_ttl carries over the deadline of the live key being replaced,
+// the live value found before the write is returned either way
+pub fn add_if(
+    db: usize,
+    key: &str,
+    value: &str,
+    expires_at: Option<SystemTime>,
+    nx: bool,
+    xx: bool,
+    keep_ttl: bool,
+) -> Result<(bool, Option<CommandData>), StorageError> {
+    let mut storage_data = lock_store();
+
+    let previous = storage_data.databases[db]
+        .get(key)
+        .filter(|cd| !cd.is_expired())
+        .cloned();
+
+    if (nx && previous.is_some()) || (xx && previous.is_none()) {
+        return Ok((false, previous));
+    }
+
+    let expires_at = if keep_ttl {
+        previous.as_ref().and_then(|cd| cd.expires_at)
     } else {
-        StorageData {
-            data: HashMap::new(),
+        expires_at
+    };
+
+    let command_data =
+        CommandData::new(key, value.as_bytes().to_vec(), expires_at).replacing(previous.as_ref());
+
+    storage_data.databases[db].insert(command_data.key.clone(), command_data);
+
+    save_store(&storage_data)?;
+
+    Ok((true, previous))
+}
+
+// all pairs are written under one lock with a single save
+pub fn add_many(db: usize, pairs: &[(&str, &str)]) -> Result<bool, StorageError> {
+    let mut storage_data = lock_store();
+
+    insert_many(&mut storage_data, db, pairs);
+    save_store(&storage_data)?;
+
+    Ok(true)
+}
+
+// nothing is written when any of the keys is live, expired ones count as absent
+pub fn add_many_if_absent(db: usize, pairs: &[(&str, &str)]) -> Result<bool, StorageError> {
+    let mut storage_data = lock_store();
+
+    if pairs.iter().any(|(key, _)| {
+        storage_data.databases[db]
+            .get(*key)
+            .is_some_and(|cd| !cd.is_expired())
+    }) {
+        return Ok(false);
+    }
+
+    insert_many(&mut storage_data, db, pairs);
+    save_store(&storage_data)?;
+
+    Ok(true)
+}
+
+fn insert_many(storage_data: &mut StorageData, db: usize, pairs: &[(&str, &str)]) {
+    for (key, value) in pairs {
+        let previous = storage_data.databases[db]
+            .get(*key)
+            .filter(|cd| !cd.is_expired());
+
+        let command_data =
+            CommandData::new(key, value.as_bytes().to_vec(), None).replacing(previous);
+
+        storage_data.databases[db].insert(command_data.key.clone(), command_data);
+    }
+}
+
+// expired entries count as absent and get replaced
+pub fn add_if_absent(db: usize, key: &str, value: &str) -> Result<bool, StorageError> {
+    let mut storage_data = lock_store();
+
+    if storage_data.databases[db]
+        .get(key)
+        .is_some_and(|cd| !cd.is_expired())
+    {
+        return Ok(false);
+    }
+
+    let command_data = CommandData::new(key, value.as_bytes().to_vec(), None);
+
+    storage_data.databases[db].insert(command_data.key.clone(), command_data);
+
+    save_store(&storage_data)?;
+
+    Ok(true)
+}
+
+// the new value is stored without a TTL, the previous one is returned if it was live
+pub fn get_and_set(
+    db: usize,
+    key: &str,
+    new_value: &str,
+) -> Result<Option<CommandData>, StorageError> {
+    let mut storage_data = lock_store();
+
+    let previous = storage_data.databases[db]
+        .get(key)
+        .filter(|cd| !cd.is_expired())
+        .cloned();
+
+    let command_data =
+        CommandData::new(key, new_value.as_bytes().to_vec(), None).replacing(previous.as_ref());
+
+    storage_data.databases[db].insert(command_data.key.clone(), command_data);
+
+    save_store(&storage_data)?;
+
+    Ok(previous)
+}
+
+pub fn get(db: usize, key: &str) -> Option<CommandData> {
+    println!("KEY: {}", key);
+    let mut storage_data = lock_store();
+
+    let cd = storage_data.databases[db].get_mut(key)?;
+    cd.record_access();
+
+    Some(cd.clone())
+}
+
+// bumps the access metadata of every live key like a read does, nothing is saved
+pub fn touch(db: usize, keys: &[String]) -> usize {
+    let mut storage_data = lock_store();
+
+    keys.iter()
+        .filter(
+            |key| match storage_data.databases[db].get_mut(key.as_str()) {
+                Some(cd) if !cd.is_expired() => {
+                    cd.record_access();
+                    true
+                }
+                _ => false,
+            },
+        )
+        .count()
+}
+
+// same as get but leaves last_accessed and lfu_freq alone, for introspection like OBJECT
+pub fn peek(db: usize, key: &str) -> Option<CommandData> {
+    lock_store().databases[db].get(key).cloned()
+}
+
+// one lock for all keys so the values come from the same snapshot, expired ones are None
+pub fn get_many(db: usize, keys: &[String]) -> Vec<Option<CommandData>> {
+    let storage_data = lock_store();
+
+    keys.iter()
+        .map(|key| {
+            storage_data.databases[db]
+                .get(key)
+                .filter(|cd| !cd.is_expired())
+                .cloned()
+        })
+        .collect()
+}
+
+// expired entries still sitting in the map are not counted
+pub fn live_count(db: usize) -> usize {
+    lock_store().databases[db]
+        .values()
+        .filter(|cd| !cd.is_expired())
+        .count()
+}
+
+// live keys matching a glob pattern, in no particular order
+pub fn keys(db: usize, pattern: &str) -> Vec<String> {
+    lock_store().databases[db]
+        .values()
+        .filter(|cd| !cd.is_expired() && glob::matches(pattern.as_bytes(), cd.key.as_bytes()))
+        .map(|cd| cd.key.clone())
+        .collect()
+}
+
+// keys are visited in order of a fixed hash and the cursor is the hash to resume from,
+// so adding or removing keys between calls never shifts the position: every key present
+// for the whole iteration is returned at least once, keys sharing the hash at a batch edge
+// may come back twice, 0 as the next cursor means the iteration is done
+pub fn scan(
+    db: usize,
+    cursor: u64,
+    match_pattern: Option<&str>,
+    count: usize,
+) -> (u64, Vec<String>) {
+    let storage_data = lock_store();
+
+    let mut keys = storage_data.databases[db]
+        .values()
+        .filter(|cd| !cd.is_expired())
+        .map(|cd| (scan_position(&cd.key), cd.key.as_str()))
+        .filter(|(position, _)| *position >= cursor)
+        .collect::<Vec<(u64, &str)>>();
+
+    keys.sort_unstable();
+
+    let next_cursor = keys.get(count).map_or(0, |(position, _)| *position);
+
+    let batch = keys
+        .iter()
+        .take(count)
+        .filter(|(_, key)| {
+            match_pattern.is_none_or(|pattern| glob::matches(pattern.as_bytes(), key.as_bytes()))
+        })
+        .map(|(_, key)| key.to_string())
+        .collect();
+
+    (next_cursor, batch)
+}
+
+// DefaultHasher::new always uses the same keys, 0 is kept free to mark the end of a scan
+fn scan_position(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(key.as_bytes());
+
+    hasher.finish().max(1)
+}
+
+// picks with a randomly seeded hasher since there is no rand dependency
+pub fn random_key(db: usize) -> Option<String> {
+    let storage_data = lock_store();
+
+    let keys = storage_data.databases[db]
+        .values()
+        .filter(|cd| !cd.is_expired())
+        .map(|cd| cd.key.as_str())
+        .collect::<Vec<&str>>();
+
+    if keys.is_empty() {
+        return None;
+    }
+
+    let seed = RandomState::new().build_hasher().finish();
+
+    Some(keys[(seed % keys.len() as u64) as usize].to_string())
+}
+
+pub fn exists(db: usize, key: &str) -> bool {
+    peek(db, key).is_some_and(|cd| !cd.is_expired())
+}
+
+// an expired entry is purged as well, but not counted as removed
+pub fn remove(db: usize, key: &str) -> Result<bool, StorageError> {
+    let mut storage_data = lock_store();
+
+    match storage_data.databases[db].remove(key) {
+        None => Ok(false),
+        Some(cd) => {
+            save_store(&storage_data)?;
+
+            Ok(!cd.is_expired())
         }
+    }
+}
+
+// the keys are gone from the map as soon as this returns, dropping the values and
+// rewriting file_name() happen later on the reclaim thread, only live keys are counted
+pub fn unlink(db: usize, keys: &[String]) -> usize {
+    let mut storage_data = lock_store();
+
+    let detached = keys
+        .iter()
+        .filter_map(|key| storage_data.databases[db].remove(key.as_str()))
+        .collect::<Vec<CommandData>>();
+
+    drop(storage_data);
+
+    let unlinked = detached.iter().filter(|cd| !cd.is_expired()).count();
+
+    if !detached.is_empty() {
+        reclaim_queue()
+            .send(detached)
+            .expect("reclaim thread is running");
+    }
+
+    unlinked
+}
+
+fn reclaim_queue() -> &'static Sender<Vec<CommandData>> {
+    RECLAIM_QUEUE.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<Vec<CommandData>>();
+
+        thread::spawn(move || {
+            for detached in receiver {
+                drop(detached);
+
+                if let Err(e) = save_store(&lock_store()) {
+                    println!("UNLINK: {e}");
+                }
+            }
+        });
+
+        sender
+    })
+}
+
+pub fn flush_db(db: usize) -> Result<(), StorageError> {
+    let mut storage_data = lock_store();
+
+    storage_data.databases[db].clear();
+
+    save_store(&storage_data)
+}
+
+// every database is cleared, storage.json is left with empty ones
+pub fn flush_all() -> Result<(), StorageError> {
+    let mut storage_data = lock_store();
+
+    storage_data.databases.iter_mut().for_each(HashMap::clear);
+
+    save_store(&storage_data)
+}
+
+// an expired entry is purged as well, but reported as missing
+pub fn get_and_remove(db: usize, key: &str) -> Result<Option<CommandData>, StorageError> {
+    let mut storage_data = lock_store();
+
+    let Some(removed) = storage_data.databases[db].remove(key) else {
+        return Ok(None);
     };
 
-    let command_data = CommandData {
-        key: key.to_string(),
-        value: value.to_string(),
-        created_at: SystemTime::now(),
-        expires_for,
+    save_store(&storage_data)?;
+
+    Ok(Some(removed).filter(|cd| !cd.is_expired()))
+}
+
+// moves the value and its TTL to dst, nx leaves a live dst untouched and returns false
+pub fn rename(db: usize, src: &str, dst: &str, nx: bool) -> Result<bool, StorageError> {
+    let mut storage_data = lock_store();
+
+    if storage_data.databases[db]
+        .get(src)
+        .is_none_or(|cd| cd.is_expired())
+    {
+        return Err(StorageError::NoSuchKey);
+    }
+
+    if src == dst {
+        return Ok(!nx);
+    }
+
+    if nx
+        && storage_data.databases[db]
+            .get(dst)
+            .is_some_and(|cd| !cd.is_expired())
+    {
+        return Ok(false);
+    }
+
+    let mut command_data = storage_data.databases[db].remove(src).unwrap();
+    command_data.key = dst.to_string();
+
+    storage_data.databases[db].insert(command_data.key.clone(), command_data);
+
+    save_store(&storage_data)?;
+
+    Ok(true)
+}
+
+// duplicates the value and its TTL into dst_db, a live dst is only overwritten with replace
+pub fn copy(
+    src_db: usize,
+    src: &str,
+    dst_db: usize,
+    dst: &str,
+    replace: bool,
+) -> Result<bool, StorageError> {
+    let mut storage_data = lock_store();
+
+    let Some(mut command_data) = storage_data.databases[src_db]
+        .get(src)
+        .filter(|cd| !cd.is_expired())
+        .cloned()
+    else {
+        return Ok(false);
     };
 
-    storage_data
-        .data
-        .insert(command_data.key.clone(), command_data.clone());
+    if !replace
+        && storage_data.databases[dst_db]
+            .get(dst)
+            .is_some_and(|cd| !cd.is_expired())
+    {
+        return Ok(false);
+    }
+
+    command_data.key = dst.to_string();
+    command_data.created_at = SystemTime::now();
+    command_data.last_accessed = SystemTime::now();
 
-    let json = serde_json::to_string(&storage_data).unwrap();
+    storage_data.databases[dst_db].insert(command_data.key.clone(), command_data);
 
-    match write_store(json) {
-        Ok(_) => Ok(true),
-        Err(e) => Err(StorageError::SaveUnsuccessful(e.to_string())),
+    save_store(&storage_data)?;
+
+    Ok(true)
+}
+
+// moves the key with its TTL to dst_db, nothing happens when it is missing in db
+// or already live in dst_db
+pub fn move_key(db: usize, key: &str, dst_db: usize) -> Result<bool, StorageError> {
+    let mut storage_data = lock_store();
+
+    if storage_data.databases[db]
+        .get(key)
+        .is_none_or(|cd| cd.is_expired())
+        || storage_data.databases[dst_db]
+            .get(key)
+            .is_some_and(|cd| !cd.is_expired())
+    {
+        return Ok(false);
     }
+
+    let command_data = storage_data.databases[db].remove(key).unwrap();
+
+    storage_data.databases[dst_db].insert(command_data.key.clone(), command_data);
+
+    save_store(&storage_data)?;
+
+    Ok(true)
 }
 
-pub fn get(key: &str) -> Option<CommandData> {
-    println!("KEY: {}", key);
-    match read_store() {
-        None => None,
-        Some(storage_data) => {
-            storage_data.data.get(key).map(|cd| cd.clone())
+// what DUMP serializes, the TTL travels separately as the RESTORE argument
+#[derive(Serialize, Deserialize)]
+struct DumpPayload {
+    value_type: ValueType,
+    value: Vec<u8>,
+}
+
+// JSON followed by the CRC64 of that JSON as 16 hex digits, all of it printable
+// so the payload survives the line based protocol reader
+pub fn dump(db: usize, key: &str) -> Option<String> {
+    let storage_data = lock_store();
+
+    let cd = storage_data.databases[db]
+        .get(key)
+        .filter(|cd| !cd.is_expired())?;
+
+    let json = serde_json::to_string(&DumpPayload {
+        value_type: cd.value_type,
+        value: cd.value.clone(),
+    })
+    .unwrap();
+
+    Some(format!("{json}{:016x}", crc64::checksum(json.as_bytes())))
+}
+
+// a live key is only overwritten with replace, otherwise false is returned,
+// a deadline already in the past deletes the key instead of writing it
+pub fn restore(
+    db: usize,
+    key: &str,
+    payload: &str,
+    expires_at: Option<SystemTime>,
+    replace: bool,
+) -> Result<bool, StorageError> {
+    let (json, checksum) = payload
+        .split_at_checked(payload.len().saturating_sub(16))
+        .ok_or(StorageError::BadPayload)?;
+
+    if u64::from_str_radix(checksum, 16).ok() != Some(crc64::checksum(json.as_bytes())) {
+        return Err(StorageError::BadPayload);
+    }
+
+    let dump_payload =
+        serde_json::from_str::<DumpPayload>(json).map_err(|_| StorageError::BadPayload)?;
+
+    let mut storage_data = lock_store();
+
+    if !replace
+        && storage_data.databases[db]
+            .get(key)
+            .is_some_and(|cd| !cd.is_expired())
+    {
+        return Ok(false);
+    }
+
+    let mut command_data = CommandData::new(key, dump_payload.value, expires_at);
+    command_data.value_type = dump_payload.value_type;
+
+    if command_data.is_expired() {
+        storage_data.databases[db].remove(key);
+    } else {
+        storage_data.databases[db].insert(command_data.key.clone(), command_data);
+    }
+
+    save_store(&storage_data)?;
+
+    Ok(true)
+}
+
+// None clears the TTL, a deadline in the past deletes the key after reading it
+pub fn get_and_set_expiry(
+    db: usize,
+    key: &str,
+    expires_at: Option<SystemTime>,
+) -> Result<Option<CommandData>, StorageError> {
+    let mut storage_data = lock_store();
+
+    let Some(cd) = storage_data.databases[db]
+        .get_mut(key)
+        .filter(|cd| !cd.is_expired())
+    else {
+        return Ok(None);
+    };
+
+    cd.expires_at = expires_at;
+    let command_data = cd.clone();
+
+    if command_data.is_expired() {
+        storage_data.databases[db].remove(key);
+    }
+
+    save_store(&storage_data)?;
+
+    Ok(Some(command_data))
+}
+
+// allows gets the current deadline of the live key and decides under the same lock,
+// None clears the TTL and a deadline already in the past deletes the key
+pub fn set_expiry_if(
+    db: usize,
+    key: &str,
+    expires_at: Option<SystemTime>,
+    allows: impl FnOnce(Option<SystemTime>) -> bool,
+) -> Result<bool, StorageError> {
+    let mut storage_data = lock_store();
+
+    match storage_data.databases[db].get_mut(key) {
+        Some(cd) if !cd.is_expired() => {
+            cd.record_access();
+
+            if !allows(cd.expires_at) {
+                return Ok(false);
+            }
+
+            cd.expires_at = expires_at;
+
+            if cd.is_expired() {
+                storage_data.databases[db].remove(key);
+            }
+        }
+        _ => return Ok(false),
+    }
+
+    save_store(&storage_data)?;
+
+    Ok(true)
+}
+
+pub fn append(db: usize, key: &str, suffix: &str) -> Result<usize, StorageError> {
+    let mut storage_data = lock_store();
+
+    let len = match storage_data.databases[db].get_mut(key) {
+        Some(cd) if !cd.is_expired() => {
+            cd.value.extend_from_slice(suffix.as_bytes());
+            cd.record_access();
+            cd.value.len()
+        }
+        _ => {
+            let command_data = CommandData::new(key, suffix.as_bytes().to_vec(), None);
+
+            storage_data.databases[db].insert(command_data.key.clone(), command_data);
+
+            suffix.len()
+        }
+    };
+
+    save_store(&storage_data)?;
+
+    Ok(len)
+}
+
+// pads with zero bytes up to offset, an empty value never creates a missing key
+pub fn set_range(db: usize, key: &str, offset: usize, value: &str) -> Result<usize, StorageError> {
+    let mut storage_data = lock_store();
+
+    let current = storage_data.databases[db]
+        .get(key)
+        .filter(|cd| !cd.is_expired());
+
+    if current.is_none() && value.is_empty() {
+        return Ok(0);
+    }
+
+    let mut command_data = current
+        .cloned()
+        .unwrap_or_else(|| CommandData::new(key, vec![], None));
+
+    if !value.is_empty() {
+        let end = offset + value.len();
+
+        if command_data.value.len() < end {
+            command_data.value.resize(end, 0);
+        }
+
+        command_data.value[offset..end].copy_from_slice(value.as_bytes());
+    }
+
+    if current.is_some() {
+        command_data.record_access();
+    }
+
+    let len = command_data.value.len();
+
+    storage_data.databases[db].insert(command_data.key.clone(), command_data);
+
+    save_store(&storage_data)?;
+
+    Ok(len)
+}
+
+// missing and expired keys start from 0, the TTL of a live key is kept
+pub fn increment_by(db: usize, key: &str, delta: i64) -> Result<i64, StorageError> {
+    let mut storage_data = lock_store();
+
+    let current = storage_data.databases[db]
+        .get(key)
+        .filter(|cd| !cd.is_expired());
+
+    let value = match current {
+        None => 0,
+        Some(cd) => cd.parse_int().ok_or(StorageError::NotInteger)?,
+    };
+
+    let value = value.checked_add(delta).ok_or(StorageError::Overflow)?;
+
+    let command_data = CommandData::new(
+        key,
+        value.to_string().into_bytes(),
+        current.and_then(|cd| cd.expires_at),
+    )
+    .replacing(current);
+
+    storage_data.databases[db].insert(command_data.key.clone(), command_data);
+
+    save_store(&storage_data)?;
+
+    Ok(value)
+}
+
+// same as increment_by, the stored value is the formatted result so GET returns it as is
+pub fn increment_by_float(db: usize, key: &str, delta: f64) -> Result<String, StorageError> {
+    let mut storage_data = lock_store();
+
+    let current = storage_data.databases[db]
+        .get(key)
+        .filter(|cd| !cd.is_expired());
+
+    let value = match current {
+        None => 0.0,
+        Some(cd) => cd.parse_value::<f64>().ok_or(StorageError::NotFloat)?,
+    };
+
+    let value = value + delta;
+
+    if !value.is_finite() {
+        return Err(StorageError::NotFinite);
+    }
+
+    let command_data = CommandData::new(
+        key,
+        format_float(value).into_bytes(),
+        current.and_then(|cd| cd.expires_at),
+    )
+    .replacing(current);
+
+    storage_data.databases[db].insert(command_data.key.clone(), command_data.clone());
+
+    save_store(&storage_data)?;
+
+    Ok(String::from_utf8(command_data.value).unwrap())
+}
+
+// 15 significant digits in plain decimal notation with trailing zeros trimmed,
+// so 3.0 is "3", 3.1415e3 is "3141.5" and 0.1 + 0.2 is "0.3"
+fn format_float(value: f64) -> String {
+    if value == 0.0 {
+        return "0".to_string();
+    }
+
+    let integer_digits = value.abs().log10().floor() as i32 + 1;
+    let decimals = (15 - integer_digits).clamp(0, 340) as usize;
+    let formatted = format!("{value:.decimals$}");
+
+    if formatted.contains('.') {
+        formatted
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string()
+    } else {
+        formatted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn upgrade_turns_expires_for_into_a_deadline() {
+        let json = r#"{"data":{
+            "volatile":{"key":"volatile","value":"v","created_at":{"secs_since_epoch":0,"nanos_since_epoch":0},"expires_for":{"secs":1,"nanos":0}},
+            "persistent":{"key":"persistent","value":"v","created_at":{"secs_since_epoch":0,"nanos_since_epoch":0},"expires_for":null}
+        }}"#;
+
+        let storage_data = serde_json::from_str::<StorageData>(json).unwrap().upgrade();
+        let volatile = &storage_data.databases[0]["volatile"];
+        let persistent = &storage_data.databases[0]["persistent"];
+
+        assert_eq!(
+            volatile.expires_at,
+            Some(UNIX_EPOCH + Duration::from_secs(1))
+        );
+        assert!(volatile.is_expired());
+        assert_eq!(persistent.expires_at, None);
+        assert!(!persistent.is_expired());
+    }
+
+    #[test]
+    fn values_are_saved_as_text_unless_binary() {
+        let text = CommandData::new("text", "héllo".as_bytes().to_vec(), None);
+        let binary = CommandData::new("binary", vec![0xff, 0x00], None);
+
+        let text_json = serde_json::to_value(&text).unwrap();
+        let binary_json = serde_json::to_value(&binary).unwrap();
+
+        assert_eq!(text_json["value"], "héllo");
+        assert_eq!(binary_json["value"], serde_json::json!([255, 0]));
+
+        let text_back = serde_json::from_value::<CommandData>(text_json).unwrap();
+        let binary_back = serde_json::from_value::<CommandData>(binary_json).unwrap();
+
+        assert_eq!(text_back.value, text.value);
+        assert_eq!(binary_back.value, binary.value);
+    }
+
+    #[test]
+    fn concurrent_get_and_set_never_see_the_same_old_value() {
+        add(0, "getset:race", "initial", None).unwrap();
+
+        let threads = (0..2)
+            .map(|thread| {
+                thread::spawn(move || {
+                    (0..50)
+                        .map(|n| {
+                            get_and_set(0, "getset:race", &format!("{thread}-{n}"))
+                                .unwrap()
+                                .map(|cd| cd.value)
+                        })
+                        .collect::<Vec<Option<Vec<u8>>>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut seen = threads
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .map(Option::unwrap)
+            .collect::<Vec<Vec<u8>>>();
+        let observed = seen.len();
+
+        seen.sort();
+        seen.dedup();
+
+        assert_eq!(seen.len(), observed);
+    }
+
+    // database 15 is left to this test so no other key ends up in the count
+    #[test]
+    fn live_count_skips_expired_keys() {
+        let past = SystemTime::now() - Duration::from_secs(1);
+
+        flush_db(15).unwrap();
+        add_many(15, &[("a", "1"), ("b", "2"), ("c", "3")]).unwrap();
+        add(15, "d", "4", Some(past)).unwrap();
+        add(15, "e", "5", Some(past)).unwrap();
+
+        assert_eq!(live_count(15), 3);
+    }
+
+    #[test]
+    fn add_many_if_absent_writes_nothing_when_one_key_exists() {
+        for key in ["msetnx:1", "msetnx:2", "msetnx:3"] {
+            remove(0, key).unwrap();
         }
+
+        add(0, "msetnx:2", "existing", None).unwrap();
+
+        let written = add_many_if_absent(
+            0,
+            &[("msetnx:1", "a"), ("msetnx:2", "b"), ("msetnx:3", "c")],
+        )
+        .unwrap();
+
+        assert!(!written);
+        assert!(peek(0, "msetnx:1").is_none());
+        assert_eq!(peek(0, "msetnx:2").unwrap().value, b"existing");
+        assert!(peek(0, "msetnx:3").is_none());
+    }
+
+    #[test]
+    fn format_float_uses_plain_decimals() {
+        assert_eq!(format_float(3.1415e3), "3141.5");
+        assert_eq!(format_float(-2.5), "-2.5");
+        assert_eq!(format_float(1e-10), "0.0000000001");
+        assert_eq!(format_float(-0.000123), "-0.000123");
+    }
+
+    #[test]
+    fn increment_by_float_handles_negative_and_tiny_deltas() {
+        add(0, "incrbyfloat:delta", "10.5", None).unwrap();
+
+        assert_eq!(
+            increment_by_float(0, "incrbyfloat:delta", -5.0).unwrap(),
+            "5.5"
+        );
+        assert_eq!(
+            increment_by_float(0, "incrbyfloat:delta", -10.0).unwrap(),
+            "-4.5"
+        );
+        assert_eq!(
+            increment_by_float(0, "incrbyfloat:delta", 0.000001).unwrap(),
+            "-4.499999"
+        );
+    }
+
+    #[test]
+    fn increment_by_float_rejects_a_stored_non_float() {
+        add(0, "incrbyfloat:text", "not a float", None).unwrap();
+
+        assert!(matches!(
+            increment_by_float(0, "incrbyfloat:text", 1.0),
+            Err(StorageError::NotFloat)
+        ));
+        assert_eq!(peek(0, "incrbyfloat:text").unwrap().value, b"not a float");
+    }
+
+    #[test]
+    fn format_float_trims_trailing_zeros() {
+        assert_eq!(format_float(3.0), "3");
+        assert_eq!(format_float(0.1 + 0.2), "0.3");
+        assert_eq!(format_float(0.0), "0");
+    }
+
+    #[test]
+    fn increment_by_float_does_not_accumulate_noise() {
+        add(0, "incrbyfloat:steps", "0", None).unwrap();
+
+        for _ in 0..100 {
+            increment_by_float(0, "incrbyfloat:steps", 0.1).unwrap();
+        }
+
+        assert_eq!(peek(0, "incrbyfloat:steps").unwrap().value, b"10");
+    }
+
+    // database 14 is left to this test so only its own keys are scanned
+    #[test]
+    fn scan_returns_keys_present_throughout_despite_changes() {
+        flush_db(14).unwrap();
+
+        let stable = (0..50)
+            .map(|n| format!("stable:{n}"))
+            .collect::<Vec<String>>();
+
+        for key in &stable {
+            add(14, key, "v", None).unwrap();
+        }
+
+        for n in 0..20 {
+            add(14, &format!("volatile:{n}"), "v", None).unwrap();
+        }
+
+        let mut returned = vec![];
+        let mut cursor = 0;
+        let mut calls = 0;
+
+        loop {
+            let (next_cursor, batch) = scan(14, cursor, None, 5);
+            returned.extend(batch);
+
+            // keys come and go between calls, only the stable ones are guaranteed
+            add(14, &format!("added:{calls}"), "v", None).unwrap();
+            remove(14, &format!("volatile:{calls}")).unwrap();
+
+            calls += 1;
+            cursor = next_cursor;
+
+            if cursor == 0 {
+                break;
+            }
+
+            assert!(calls < 100, "scan never finished");
+        }
+
+        for key in &stable {
+            assert!(returned.contains(key), "{key} was never returned");
+        }
+    }
+
+    #[test]
+    fn concurrent_gt_expiries_keep_the_latest_deadline() {
+        let base = SystemTime::now() + Duration::from_secs(60);
+        add(0, "expire:gt:race", "v", Some(base)).unwrap();
+
+        let threads = (1..=8)
+            .map(|n| {
+                thread::spawn(move || {
+                    let expires_at = base + Duration::from_secs(n);
+
+                    set_expiry_if(0, "expire:gt:race", Some(expires_at), |current| {
+                        current.is_some_and(|current| expires_at > current)
+                    })
+                    .unwrap();
+                })
+            })
+            .collect::<Vec<_>>();
+
+        threads
+            .into_iter()
+            .for_each(|handle| handle.join().unwrap());
+
+        assert_eq!(
+            peek(0, "expire:gt:race").unwrap().expires_at,
+            Some(base + Duration::from_secs(8))
+        );
+    }
+
+    #[test]
+    fn set_expiry_if_deletes_on_a_past_deadline() {
+        add(0, "expire:past", "v", None).unwrap();
+
+        let past = SystemTime::now() - Duration::from_secs(1);
+
+        assert!(!set_expiry_if(0, "expire:past", Some(past), |_| false).unwrap());
+        assert!(peek(0, "expire:past").is_some());
+        assert!(set_expiry_if(0, "expire:past", Some(past), |_| true).unwrap());
+        assert!(peek(0, "expire:past").is_none());
+    }
+
+    #[test]
+    fn encoding_is_int_only_for_canonical_integers() {
+        let encoding =
+            |value: &str| CommandData::new("k", value.as_bytes().to_vec(), None).encoding();
+
+        assert_eq!(encoding("12345"), "int");
+        assert_eq!(encoding("-7"), "int");
+        assert_eq!(encoding("007"), "embstr");
+        assert_eq!(encoding("+5"), "embstr");
+        assert_eq!(encoding(" 5"), "embstr");
+        assert_eq!(encoding("hello"), "embstr");
+        assert_eq!(encoding(&"x".repeat(45)), "raw");
     }
 }